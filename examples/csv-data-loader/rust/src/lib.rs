@@ -1,22 +1,22 @@
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    io::{Cursor, Read},
-};
+use std::collections::BTreeSet;
 
 use foxglove_data_loader::{
     DataLoader, DataLoaderArgs, Initialization, Message, MessageIterator, MessageIteratorArgs,
-    reader::{self},
 };
 
 use anyhow::bail;
 use csv::StringRecord;
+use data_loader_utils::{
+    TimeIndex,
+    reader::{self, Reader},
+};
 use serde_json::json;
 
 #[derive(Default)]
 struct CsvDataLoader {
     path: String,
-    /// Index of timestamp to byte offset
-    indexes: BTreeMap<u64, u64>,
+    /// Index of timestamp to byte offset of every row
+    indexes: TimeIndex<u64>,
     /// The index of the field containing timestamp
     log_time_index: usize,
     /// The keys from the first row of the CSV
@@ -62,6 +62,7 @@ impl DataLoader for CsvDataLoader {
 
         let mut record = StringRecord::new();
         let mut position = reader.position().byte();
+        let mut entries = Vec::new();
 
         // Read the entire file to build up an index of timestamps to byte position.
         // Later on we'll use this index to make sure we can immediately start reading from the
@@ -69,23 +70,15 @@ impl DataLoader for CsvDataLoader {
         // time, but it will mean playback is snappy later on.
         while reader.read_record(&mut record)? {
             let timestamp_nanos: u64 = record[log_time_index].parse()?;
-            self.indexes.insert(timestamp_nanos, position);
+            entries.push((timestamp_nanos, position));
             position = reader.position().byte();
         }
+        // The rows don't have to be in time order, so they're sorted once they've all been read
+        self.indexes = entries.into_iter().collect();
 
         let mut builder = Initialization::builder()
-            .start_time(
-                self.indexes
-                    .first_key_value()
-                    .map(|(timestamp, _)| *timestamp)
-                    .unwrap_or(0),
-            )
-            .end_time(
-                self.indexes
-                    .last_key_value()
-                    .map(|(timestamp, _)| *timestamp)
-                    .unwrap_or(0),
-            );
+            .start_time(self.indexes.start_time().unwrap_or(0))
+            .end_time(self.indexes.end_time().unwrap_or(0));
 
         for (i, key) in self.keys.iter().enumerate() {
             // Don't add a channel for the column used for log time
@@ -107,31 +100,24 @@ impl DataLoader for CsvDataLoader {
         &mut self,
         args: MessageIteratorArgs,
     ) -> Result<Self::MessageIterator, Self::Error> {
-        let requested_channel_id = args.channels.into_iter().collect();
-
-        match self.indexes.range(args.start_time.unwrap_or(0)..).next() {
-            Some((_, byte_offset)) => {
-                let reader = reader::open(&self.path);
-                reader.seek(*byte_offset);
-
-                Ok(CsvMessageIterator {
-                    row_to_flush: Default::default(),
-                    log_time_index: self.log_time_index,
-                    requested_channel_id,
-                    reader: csv::ReaderBuilder::new()
-                        .has_headers(false)
-                        .trim(csv::Trim::All)
-                        .from_reader(Box::new(reader)),
-                })
-            }
-            // If there is no byte offset (we've gone past the last timestamp), return empty iter
-            None => Ok(CsvMessageIterator {
-                log_time_index: self.log_time_index,
-                row_to_flush: Default::default(),
-                requested_channel_id: Default::default(),
-                reader: csv::Reader::from_reader(Box::new(Cursor::new([]))),
-            }),
-        }
+        // The rows in the file may not be sorted by timestamp, so they're read in the order of
+        // the index, which is.
+        let rows: Vec<(u64, u64)> = self
+            .indexes
+            .range(args.start_time, args.end_time)
+            .map(|(timestamp, byte_offset)| (timestamp, *byte_offset))
+            .collect();
+
+        Ok(CsvMessageIterator {
+            row_to_flush: Default::default(),
+            log_time_index: self.log_time_index,
+            requested_channel_id: args.channels.into_iter().collect(),
+            rows: rows.into_iter(),
+            reader: csv::ReaderBuilder::new()
+                .has_headers(false)
+                .trim(csv::Trim::All)
+                .from_reader(reader::open(&self.path)),
+        })
     }
 }
 
@@ -139,7 +125,9 @@ struct CsvMessageIterator {
     row_to_flush: Vec<Message>,
     log_time_index: usize,
     requested_channel_id: BTreeSet<u16>,
-    reader: csv::Reader<Box<dyn Read>>,
+    /// The timestamp and byte offset of each row left to read, in time order
+    rows: std::vec::IntoIter<(u64, u64)>,
+    reader: csv::Reader<Reader>,
 }
 
 /// Try and coerce the string into a JSON value.
@@ -167,6 +155,18 @@ impl MessageIterator for CsvMessageIterator {
                 return Some(Ok(message));
             }
 
+            let (timestamp, byte_offset) = self.rows.next()?;
+
+            // When the rows are in time order the next row is the one after the last, otherwise
+            // jump to it.
+            if self.reader.position().byte() != byte_offset {
+                let mut position = csv::Position::new();
+                position.set_byte(byte_offset);
+                if let Err(e) = self.reader.seek(position) {
+                    return Some(Err(e.into()));
+                }
+            }
+
             let mut columns = StringRecord::new();
 
             match self.reader.read_record(&mut columns) {
@@ -180,14 +180,6 @@ impl MessageIterator for CsvMessageIterator {
                 Ok(true) => {}
             }
 
-            // Get the log time for the row. This will need to be on every message.
-            let timestamp = match columns[self.log_time_index].parse::<u64>() {
-                Ok(t) => t,
-                Err(e) => {
-                    return Some(Err(e.into()));
-                }
-            };
-
             for (index, cell) in columns.iter().enumerate() {
                 // Don't emit the timestamp column as a message
                 if index == self.log_time_index {
@@ -217,3 +209,131 @@ impl MessageIterator for CsvMessageIterator {
 }
 
 foxglove_data_loader::export!(CsvDataLoader);
+
+#[cfg(test)]
+mod tests {
+    use data_loader_utils::{
+        conformance,
+        harness::{Harness, count_by_channel},
+    };
+
+    use super::*;
+
+    const SAMPLE_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../sample_data.csv");
+    const START_TIME: u64 = 1_699_123_450_000_000_000;
+
+    fn value(message: &Message) -> serde_json::Value {
+        let data: serde_json::Value = serde_json::from_slice(&message.data).unwrap();
+        data["value"].clone()
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::assert_conforms::<CsvDataLoader>(&[SAMPLE_DATA]);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let harness = Harness::<CsvDataLoader>::open(&[SAMPLE_DATA]).unwrap();
+        let info = harness.info();
+        assert_eq!(info.start_time, START_TIME);
+        assert_eq!(info.end_time, START_TIME + 9_000_000_000);
+        let topics: Vec<&str> = info.channels.iter().map(|c| c.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "/temperature",
+                "/pressure",
+                "/velocity_x",
+                "/velocity_y",
+                "/velocity_z",
+                "/status",
+                "/latitude",
+                "/longitude"
+            ]
+        );
+        for channel in &info.channels {
+            assert_eq!(channel.message_encoding, "json");
+            assert_eq!(channel.message_count, Some(10));
+        }
+    }
+
+    #[test]
+    fn test_read_all() {
+        let mut harness = Harness::<CsvDataLoader>::open(&[SAMPLE_DATA]).unwrap();
+        let messages = harness.all_messages().unwrap();
+        assert_eq!(messages.len(), 80);
+        assert!(
+            count_by_channel(&messages)
+                .values()
+                .all(|&count| count == 10)
+        );
+
+        let temperature = harness.info().channel("/temperature").id;
+        let status = harness.info().channel("/status").id;
+        let first = |channel_id| {
+            messages
+                .iter()
+                .find(|message| message.channel_id == channel_id)
+                .unwrap()
+        };
+        assert_eq!(first(temperature).log_time, START_TIME);
+        assert_eq!(value(first(temperature)), json!(25.5));
+        assert_eq!(value(first(status)), json!(true));
+    }
+
+    #[test]
+    fn test_read_range() {
+        let mut harness = Harness::<CsvDataLoader>::open(&[SAMPLE_DATA]).unwrap();
+        let pressure = harness.info().channel("/pressure").id;
+        let messages = harness
+            .messages(MessageIteratorArgs {
+                start_time: Some(START_TIME + 2_000_000_000),
+                end_time: Some(START_TIME + 4_000_000_000),
+                channels: vec![pressure],
+            })
+            .unwrap();
+        let values: Vec<_> = messages.iter().map(value).collect();
+        assert_eq!(values, vec![json!(1013.35), json!(1013.40), json!(1013.45)]);
+        assert!(
+            messages
+                .iter()
+                .all(|message| message.channel_id == pressure)
+        );
+    }
+
+    #[test]
+    fn test_unsorted_rows() {
+        let path = "unsorted.csv";
+        reader::insert_file(path, "timestamp_nanos,value\n10,1\n30,3\n20,2\n40,4\n");
+        conformance::assert_conforms::<CsvDataLoader>(&[path]);
+        let mut harness = Harness::<CsvDataLoader>::open(&[path]).unwrap();
+        let messages = harness
+            .messages(MessageIteratorArgs {
+                start_time: Some(15),
+                end_time: Some(35),
+                channels: harness.info().channel_ids(),
+            })
+            .unwrap();
+        let values: Vec<_> = messages.iter().map(value).collect();
+        assert_eq!(values, vec![json!(2.0), json!(3.0)]);
+    }
+
+    #[test]
+    fn test_duplicate_timestamps() {
+        let path = "duplicates.csv";
+        reader::insert_file(path, "timestamp_nanos,value\n10,1\n20,2\n20,3\n30,4\n");
+        let mut harness = Harness::<CsvDataLoader>::open(&[path]).unwrap();
+        let channel = harness.info().channel("/value").clone();
+        assert_eq!(channel.message_count, Some(4));
+        let messages = harness
+            .messages(MessageIteratorArgs {
+                start_time: Some(20),
+                end_time: Some(20),
+                channels: vec![channel.id],
+            })
+            .unwrap();
+        let values: Vec<_> = messages.iter().map(value).collect();
+        assert_eq!(values, vec![json!(2.0), json!(3.0)]);
+    }
+}
@@ -0,0 +1,299 @@
+//! Checks that a loader keeps to the rules Foxglove relies on when it plays back a file:
+//!
+//! - Message iterators only emit messages between their start and end time, and within the time
+//!   range from `initialize`.
+//! - Log times never go backwards.
+//! - Only messages on the requested channels are emitted.
+//! - Each channel has as many messages as the `message_count` it was added with.
+//! - Backfill returns at most one message for each requested channel, logged at or before the
+//!   backfill time.
+//!
+//! [`check`] reads the whole file without a time range, then smaller ranges and single channels,
+//! and requests backfill at times across the file. Any loader built from the template can run it
+//! on its fixtures:
+//!
+//! ```ignore
+//! #[test]
+//! fn test_conformance() {
+//!     conformance::assert_conforms::<MyDataLoader>(&["../fixture.bin"]);
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use foxglove_data_loader::{DataLoader, Message, MessageIterator, MessageIteratorArgs};
+
+use crate::harness::{Harness, LoaderInfo, count_by_channel};
+
+/// The number of parts the file's time range is split into to check shorter ranges and backfill.
+const PARTS: u64 = 4;
+
+/// Check the loader against each rule on the files at `paths`, returning a description of every
+/// rule it breaks. Errors from the loader are returned as errors.
+pub fn check<L>(paths: &[&str]) -> anyhow::Result<Vec<String>>
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    let mut harness = Harness::<L>::open(paths)?;
+    let info = harness.info().clone();
+    let channels = info.channel_ids();
+    let mut violations = vec![];
+
+    // Without a time range, like when Foxglove reads the whole file, only the time range from
+    // initialize bounds the messages
+    let messages = check_iter(&mut harness, None, &channels, &mut violations)?;
+    let counts = count_by_channel(&messages);
+    for channel in &info.channels {
+        let Some(expected) = channel.message_count else {
+            continue;
+        };
+        let actual = counts.get(&channel.id).copied().unwrap_or(0);
+        if actual != expected {
+            violations.push(format!(
+                "{}: message_count is {expected}, but {actual} messages were read",
+                channel.topic
+            ));
+        }
+    }
+
+    for channel_id in &channels {
+        check_iter(
+            &mut harness,
+            Some((info.start_time, info.end_time)),
+            &[*channel_id],
+            &mut violations,
+        )?;
+    }
+    let times = times(&info);
+    for range in times.windows(2) {
+        check_iter(
+            &mut harness,
+            Some((range[0], range[1])),
+            &channels,
+            &mut violations,
+        )?;
+    }
+    // A range of a single instant
+    let middle = times[times.len() / 2];
+    check_iter(
+        &mut harness,
+        Some((middle, middle)),
+        &channels,
+        &mut violations,
+    )?;
+
+    let mut backfill_times = times.clone();
+    if info.start_time > 0 {
+        backfill_times.insert(0, info.start_time - 1);
+    }
+    backfill_times.push(info.end_time.saturating_add(1));
+    for time in backfill_times {
+        check_backfill(&mut harness, time, &channels, &mut violations)?;
+        for channel_id in &channels {
+            check_backfill(&mut harness, time, &[*channel_id], &mut violations)?;
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Run [`check`] and panic with every rule the loader breaks.
+pub fn assert_conforms<L>(paths: &[&str])
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    let violations = check::<L>(paths).unwrap_or_else(|err| panic!("loader failed: {err:#}"));
+    assert!(
+        violations.is_empty(),
+        "loader broke {} rules:\n{}",
+        violations.len(),
+        violations.join("\n")
+    );
+}
+
+/// The start and end time of the file, with times splitting it into [`PARTS`] in between.
+fn times(info: &LoaderInfo) -> Vec<u64> {
+    let duration = info.end_time.saturating_sub(info.start_time);
+    let mut times: Vec<u64> = (0..=PARTS)
+        .map(|part| info.start_time + (duration as u128 * part as u128 / PARTS as u128) as u64)
+        .collect();
+    times.dedup();
+    times
+}
+
+/// Read the messages in the time `range` on `channels`, checking each message. Without a range the
+/// iterator is created without a start and end time.
+fn check_iter<L>(
+    harness: &mut Harness<L>,
+    range: Option<(u64, u64)>,
+    channels: &[u16],
+    violations: &mut Vec<String>,
+) -> anyhow::Result<Vec<Message>>
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    let messages = harness.messages(MessageIteratorArgs {
+        start_time: range.map(|(start_time, _)| start_time),
+        end_time: range.map(|(_, end_time)| end_time),
+        channels: channels.to_vec(),
+    })?;
+    let name = match range {
+        Some((start_time, end_time)) => {
+            format!("create_iter from {start_time} to {end_time} on channels {channels:?}")
+        }
+        None => format!("create_iter without a time range on channels {channels:?}"),
+    };
+    let info = harness.info();
+
+    let requested: BTreeSet<u16> = channels.iter().copied().collect();
+    let unrequested: BTreeSet<u16> = messages
+        .iter()
+        .map(|message| message.channel_id)
+        .filter(|id| !requested.contains(id))
+        .collect();
+    if !unrequested.is_empty() {
+        violations.push(format!(
+            "{name}: emitted messages on channels {unrequested:?}, which weren't requested"
+        ));
+    }
+
+    if let Some((count, first)) =
+        range.and_then(|(start_time, end_time)| outside(&messages, start_time, end_time))
+    {
+        violations.push(format!(
+            "{name}: emitted {count} messages outside the requested range, the first at {} on \
+             channel {}",
+            first.log_time, first.channel_id
+        ));
+    } else if let Some((count, first)) = outside(&messages, info.start_time, info.end_time) {
+        violations.push(format!(
+            "{name}: emitted {count} messages outside the time range from initialize, the first \
+             at {} on channel {}",
+            first.log_time, first.channel_id
+        ));
+    }
+
+    if let Some(pair) = messages
+        .windows(2)
+        .find(|pair| pair[1].log_time < pair[0].log_time)
+    {
+        violations.push(format!(
+            "{name}: log time went back from {} on channel {} to {} on channel {}",
+            pair[0].log_time, pair[0].channel_id, pair[1].log_time, pair[1].channel_id
+        ));
+    }
+
+    Ok(messages)
+}
+
+/// The number of messages logged outside of `start_time` to `end_time`, and the first of them.
+fn outside(messages: &[Message], start_time: u64, end_time: u64) -> Option<(usize, &Message)> {
+    let mut outside = messages
+        .iter()
+        .filter(|message| message.log_time < start_time || message.log_time > end_time);
+    let first = outside.next()?;
+    Some((outside.count() + 1, first))
+}
+
+/// Request backfill at `time` on `channels`, checking the messages returned.
+fn check_backfill<L>(
+    harness: &mut Harness<L>,
+    time: u64,
+    channels: &[u16],
+    violations: &mut Vec<String>,
+) -> anyhow::Result<()>
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    let messages = harness.backfill(time, channels.to_vec())?;
+    let name = format!("get_backfill at {time} on channels {channels:?}");
+
+    let mut counts: BTreeMap<u16, usize> = BTreeMap::new();
+    for message in &messages {
+        *counts.entry(message.channel_id).or_default() += 1;
+        if !channels.contains(&message.channel_id) {
+            violations.push(format!(
+                "{name}: returned a message on channel {}, which wasn't requested",
+                message.channel_id
+            ));
+        }
+        if message.log_time > time {
+            violations.push(format!(
+                "{name}: returned a message at {} on channel {}, after the backfill time",
+                message.log_time, message.channel_id
+            ));
+        }
+    }
+    for (channel_id, count) in counts {
+        if count > 1 {
+            violations.push(format!(
+                "{name}: returned {count} messages on channel {channel_id}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{reader, test_loader::LineLoader};
+
+    /// The rules broken by a loader reading a file with a fault, or none without one.
+    fn violations(fault: Option<&str>) -> Vec<String> {
+        let path = format!("conformance-{}.lines", fault.unwrap_or("none"));
+        let mut file = fault.map_or(String::new(), |fault| format!("# fault {fault}\n"));
+        for (time, topic) in [(10, "/a"), (20, "/b"), (30, "/a"), (40, "/b"), (50, "/a")] {
+            file += &format!("{time} {topic} {{\"time\":{time}}}\n");
+        }
+        reader::insert_file(&path, file);
+        check::<LineLoader>(&[&path]).unwrap()
+    }
+
+    /// Check that the loader breaks a rule with the fault, and that every violation found is of
+    /// that rule.
+    fn assert_breaks(fault: &str, rule: &str) {
+        let violations = violations(Some(fault));
+        assert!(!violations.is_empty(), "{fault}: no violations");
+        for violation in &violations {
+            assert!(violation.contains(rule), "{fault}: {violation}");
+        }
+    }
+
+    #[test]
+    fn test_conforming_loader() {
+        assert_eq!(violations(None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_broken_loaders() {
+        assert_breaks("unordered", "log time went back");
+        assert_breaks("ignore-time-range", "outside the requested range");
+        assert_breaks("short-time-range", "outside the time range from initialize");
+        assert_breaks("ignore-channels", "which weren't requested");
+        assert_breaks("wrong-message-count", "message_count is");
+    }
+
+    #[test]
+    fn test_broken_backfill() {
+        let violations = violations(Some("backfill-everything"));
+        assert!(
+            violations
+                .iter()
+                .any(|v| v.contains("after the backfill time"))
+        );
+        assert!(
+            violations
+                .iter()
+                .any(|v| v.contains("3 messages on channel 0"))
+        );
+    }
+}
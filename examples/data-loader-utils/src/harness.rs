@@ -0,0 +1,185 @@
+//! Runs a [`DataLoader`] natively, driving it the way Foxglove does: `new` and `initialize` when a
+//! file is opened, then a message iterator for each range that's played back and backfill requests
+//! when seeking.
+//!
+//! The loader has to open its files with [`crate::reader`], since the host's reader isn't
+//! available outside of Foxglove. Fixtures can be read from disk, or added in memory with
+//! [`crate::reader::insert_file`].
+
+use std::collections::BTreeMap;
+
+use foxglove_data_loader::{
+    BackfillArgs, DataLoader, DataLoaderArgs, Initialization, Message, MessageIterator,
+    MessageIteratorArgs, Severity,
+};
+
+/// What a loader reported from `initialize`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoaderInfo {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub channels: Vec<ChannelInfo>,
+    pub schemas: Vec<SchemaInfo>,
+    /// The problems to show in Foxglove's problems panel
+    pub problems: Vec<ProblemInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub id: u16,
+    pub topic: String,
+    pub message_encoding: String,
+    pub schema_id: Option<u16>,
+    /// The name of the channel's schema, if it has one
+    pub schema_name: Option<String>,
+    pub message_count: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaInfo {
+    pub id: u16,
+    pub name: String,
+    pub encoding: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProblemInfo {
+    pub severity: Severity,
+    pub message: String,
+    pub tip: Option<String>,
+}
+
+impl LoaderInfo {
+    fn from_initialization(init: Initialization) -> Self {
+        // Read the initialization the way the host receives it, as the record defined by the
+        // loader interface
+        let init: foxglove_data_loader::loader::Initialization = init.into();
+        let schema_names: BTreeMap<u16, String> = init
+            .schemas
+            .iter()
+            .map(|schema| (schema.id, schema.name.clone()))
+            .collect();
+        Self {
+            start_time: init.time_range.start_time,
+            end_time: init.time_range.end_time,
+            channels: init
+                .channels
+                .into_iter()
+                .map(|channel| ChannelInfo {
+                    id: channel.id,
+                    schema_id: channel.schema_id,
+                    schema_name: channel
+                        .schema_id
+                        .and_then(|id| schema_names.get(&id).cloned()),
+                    topic: channel.topic_name,
+                    message_encoding: channel.message_encoding,
+                    message_count: channel.message_count,
+                })
+                .collect(),
+            schemas: init
+                .schemas
+                .into_iter()
+                .map(|schema| SchemaInfo {
+                    id: schema.id,
+                    name: schema.name,
+                    encoding: schema.encoding,
+                    data: schema.data,
+                })
+                .collect(),
+            problems: init
+                .problems
+                .into_iter()
+                .map(|problem| ProblemInfo {
+                    severity: problem.severity,
+                    message: problem.message,
+                    tip: problem.tip,
+                })
+                .collect(),
+        }
+    }
+
+    /// The channel on `topic`.
+    ///
+    /// # Panics
+    ///
+    /// If there's no channel on `topic`, to keep tests short.
+    pub fn channel(&self, topic: &str) -> &ChannelInfo {
+        self.channels
+            .iter()
+            .find(|channel| channel.topic == topic)
+            .unwrap_or_else(|| panic!("no channel on {topic}"))
+    }
+
+    pub fn channel_ids(&self) -> Vec<u16> {
+        self.channels.iter().map(|channel| channel.id).collect()
+    }
+}
+
+/// An initialized loader.
+pub struct Harness<L> {
+    loader: L,
+    info: LoaderInfo,
+}
+
+impl<L> Harness<L>
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    /// Create a loader for `paths` and initialize it.
+    pub fn open(paths: &[&str]) -> anyhow::Result<Self> {
+        let mut loader = L::new(DataLoaderArgs {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        });
+        let init = loader.initialize().map_err(Into::into)?;
+        Ok(Self {
+            loader,
+            info: LoaderInfo::from_initialization(init),
+        })
+    }
+
+    pub fn info(&self) -> &LoaderInfo {
+        &self.info
+    }
+
+    pub fn loader(&mut self) -> &mut L {
+        &mut self.loader
+    }
+
+    /// Read every message from a message iterator created with `args`.
+    pub fn messages(&mut self, args: MessageIteratorArgs) -> anyhow::Result<Vec<Message>> {
+        let mut iter = self.loader.create_iter(args).map_err(Into::into)?;
+        let mut messages = vec![];
+        while let Some(message) = iter.next() {
+            messages.push(message.map_err(Into::into)?);
+        }
+        Ok(messages)
+    }
+
+    /// Read every message on every channel.
+    pub fn all_messages(&mut self) -> anyhow::Result<Vec<Message>> {
+        self.messages(MessageIteratorArgs {
+            start_time: Some(self.info.start_time),
+            end_time: Some(self.info.end_time),
+            channels: self.info.channel_ids(),
+        })
+    }
+
+    /// Request the messages to show on `channels` when seeking to `time`.
+    pub fn backfill(&mut self, time: u64, channels: Vec<u16>) -> anyhow::Result<Vec<Message>> {
+        self.loader
+            .get_backfill(BackfillArgs { time, channels })
+            .map_err(Into::into)
+    }
+}
+
+/// The number of messages on each channel.
+pub fn count_by_channel(messages: &[Message]) -> BTreeMap<u16, u64> {
+    let mut counts = BTreeMap::new();
+    for message in messages {
+        *counts.entry(message.channel_id).or_default() += 1;
+    }
+    counts
+}
@@ -0,0 +1,29 @@
+//! Building blocks shared by the Rust data loader examples.
+//!
+//! The loaders are compiled to WebAssembly on their own, so this crate is used as a path
+//! dependency rather than published.
+//!
+//! Loaders should use the [`reader`] and [`console`] modules from this crate rather than from
+//! `foxglove_data_loader`. In WebAssembly they're the host's, and natively they read files from
+//! disk or memory and collect log lines, so loaders can be run by tests on the build machine. The
+//! `harness` feature adds [`harness::Harness`] to drive a loader the way Foxglove does, and the
+//! [`conformance`] checks every loader should pass. The `runner` feature adds a command line
+//! [`runner`] that prints what a loader reads.
+
+#[cfg(all(feature = "harness", not(target_arch = "wasm32")))]
+pub mod conformance;
+#[cfg(all(feature = "harness", not(target_arch = "wasm32")))]
+pub mod harness;
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(all(feature = "runner", not(target_arch = "wasm32")))]
+pub mod runner;
+#[cfg(all(test, feature = "harness"))]
+mod test_loader;
+mod time_index;
+
+#[cfg(target_arch = "wasm32")]
+pub use foxglove_data_loader::{console, reader};
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{console, reader};
+pub use time_index::{ChannelTimeIndex, TimeIndex};
@@ -0,0 +1,157 @@
+//! Stand-ins for the host's `reader` and `console` modules when running outside of Foxglove.
+
+pub mod reader {
+    //! Files are read from memory when they were added with [`insert_file`], or from disk.
+
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        io::{self, Read, Seek, SeekFrom},
+        rc::Rc,
+    };
+
+    thread_local! {
+        static FILES: RefCell<HashMap<String, Rc<[u8]>>> = RefCell::new(HashMap::new());
+    }
+
+    /// Serve `data` as the contents of `path` to loaders on this thread, instead of reading the
+    /// file.
+    pub fn insert_file(path: &str, data: impl Into<Vec<u8>>) {
+        let data: Rc<[u8]> = data.into().into();
+        FILES.with_borrow_mut(|files| files.insert(path.to_string(), data));
+    }
+
+    /// Check that `path` can be opened, returning the error that would make [`open`] panic.
+    pub fn check(path: &str) -> io::Result<()> {
+        if FILES.with_borrow(|files| files.contains_key(path)) {
+            return Ok(());
+        }
+        std::fs::File::open(path).map(drop)
+    }
+
+    /// Open a file, like the host does when a loader runs in Foxglove.
+    ///
+    /// # Panics
+    ///
+    /// If the file wasn't inserted and can't be read from disk. Inside Foxglove the host only
+    /// passes paths it can open, so callers taking paths from elsewhere should [`check`] them.
+    pub fn open(path: &str) -> Reader {
+        let data = FILES
+            .with_borrow(|files| files.get(path).cloned())
+            .unwrap_or_else(|| match std::fs::read(path) {
+                Ok(data) => data.into(),
+                Err(err) => panic!("failed to open {path}: {err}"),
+            });
+        Reader {
+            data,
+            position: Cell::new(0),
+        }
+    }
+
+    /// A file opened with [`open`]. It has the host reader's `seek`, `position` and `size`
+    /// methods, and reads through [`Read`].
+    pub struct Reader {
+        data: Rc<[u8]>,
+        position: Cell<u64>,
+    }
+
+    impl Reader {
+        /// Move to a byte offset from the start of the file, returning the new position.
+        pub fn seek(&self, position: u64) -> u64 {
+            self.position.set(position);
+            position
+        }
+
+        pub fn position(&self) -> u64 {
+            self.position.get()
+        }
+
+        /// The length of the file in bytes.
+        pub fn size(&self) -> u64 {
+            self.data.len() as u64
+        }
+    }
+
+    impl Read for Reader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let start = self.position().min(self.size()) as usize;
+            let len = buf.len().min(self.data.len() - start);
+            buf[..len].copy_from_slice(&self.data[start..start + len]);
+            self.position.set((start + len) as u64);
+            Ok(len)
+        }
+    }
+
+    impl Seek for Reader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let position = match pos {
+                SeekFrom::Start(offset) => Some(offset),
+                SeekFrom::End(offset) => self.size().checked_add_signed(offset),
+                SeekFrom::Current(offset) => self.position().checked_add_signed(offset),
+            };
+            match position {
+                Some(position) => Ok(Reader::seek(self, position)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "seek before the start of the file",
+                )),
+            }
+        }
+    }
+}
+
+pub mod console {
+    //! Log lines are printed to stderr, where the test runner captures them, and kept so tests
+    //! can check them with [`take_logs`].
+
+    use std::cell::RefCell;
+
+    thread_local! {
+        static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub fn log(message: &str) {
+        eprintln!("{message}");
+        LOGS.with_borrow_mut(|logs| logs.push(message.to_string()));
+    }
+
+    /// The lines logged on this thread since the last call.
+    pub fn take_logs() -> Vec<String> {
+        LOGS.with_borrow_mut(std::mem::take)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+
+    use super::*;
+
+    #[test]
+    fn test_reader() {
+        reader::insert_file("memory.txt", "hello world");
+        let mut file = reader::open("memory.txt");
+        assert_eq!(file.size(), 11);
+        file.seek(6);
+        let mut text = String::new();
+        file.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "world");
+        assert_eq!(Seek::seek(&mut file, SeekFrom::End(-5)).unwrap(), 6);
+        assert_eq!(Seek::seek(&mut file, SeekFrom::Current(-1)).unwrap(), 5);
+        assert!(Seek::seek(&mut file, SeekFrom::Current(-6)).is_err());
+        // Reading past the end gives nothing
+        file.seek(100);
+        assert_eq!(Read::read(&mut file, &mut [0; 4]).unwrap(), 0);
+
+        let file = reader::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+        assert!(file.size() > 0);
+    }
+
+    #[test]
+    fn test_console() {
+        console::log("one");
+        console::log("two");
+        assert_eq!(console::take_logs(), vec!["one", "two"]);
+        assert!(console::take_logs().is_empty());
+    }
+}
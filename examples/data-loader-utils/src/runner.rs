@@ -0,0 +1,407 @@
+//! A command line runner for debugging a loader without installing it in Foxglove.
+//!
+//! The runner opens files with a loader, prints its initialization as one line of JSON, then one
+//! line of JSON for each message it reads. Messages with the `json` encoding are printed as JSON,
+//! other messages as base64. A loader's crate runs it from a binary:
+//!
+//! ```ignore
+//! fn main() -> anyhow::Result<()> {
+//!     data_loader_utils::runner::main::<MyDataLoader>()
+//! }
+//! ```
+
+use std::io::{self, Write};
+
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use foxglove_data_loader::{DataLoader, Message, MessageIterator, MessageIteratorArgs, Severity};
+use serde_json::{Value, json};
+
+use crate::{
+    harness::{Harness, LoaderInfo},
+    reader,
+};
+
+const USAGE: &str = "\
+Usage: run [OPTIONS] <FILE>...
+
+Runs the data loader on the files and prints its initialization, then every message it reads, as
+JSON lines.
+
+Options:
+  --start <TIME>      Read messages logged at or after TIME, in nanoseconds since the epoch
+  --end <TIME>        Read messages logged at or before TIME
+  --channel <TOPIC>   Read messages on TOPIC, a topic name or channel id. Can be repeated, the
+                      default is every channel
+  --backfill <TIME>   Print the messages backfilled when seeking to TIME, instead of reading
+  --info              Only print the initialization
+  -h, --help          Print this help";
+
+/// The command line arguments of the runner.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Args {
+    pub paths: Vec<String>,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    /// Topic names or channel ids to read, or every channel if empty
+    pub channels: Vec<String>,
+    pub backfill: Option<u64>,
+    pub info_only: bool,
+    pub help: bool,
+}
+
+impl Args {
+    /// Parse the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| anyhow!("{name} needs a value"));
+            let time = |value: String| -> anyhow::Result<u64> {
+                value
+                    .parse()
+                    .with_context(|| format!("invalid time {value:?}, expected nanoseconds"))
+            };
+            match arg.as_str() {
+                "--start" => parsed.start_time = Some(time(value(&arg)?)?),
+                "--end" => parsed.end_time = Some(time(value(&arg)?)?),
+                "--channel" => parsed.channels.push(value(&arg)?),
+                "--backfill" => parsed.backfill = Some(time(value(&arg)?)?),
+                "--info" => parsed.info_only = true,
+                "-h" | "--help" => parsed.help = true,
+                option if option.starts_with('-') => bail!("unknown option {option}"),
+                _ => parsed.paths.push(arg),
+            }
+        }
+        if parsed.paths.is_empty() && !parsed.help {
+            bail!("no files given");
+        }
+        Ok(parsed)
+    }
+}
+
+/// Run the loader with the process's arguments, writing to stdout.
+pub fn main<L>() -> anyhow::Result<()>
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{USAGE}\n");
+            return Err(err);
+        }
+    };
+    if args.help {
+        // Ignore a closed pipe, like the output below
+        let _ = writeln!(io::stdout(), "{USAGE}");
+        return Ok(());
+    }
+    let result = run::<L>(&args, &mut io::stdout().lock());
+    // Stop quietly when the output is piped into a command that exits early, like `head`
+    match result {
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Run the loader as given by `args`, writing JSON lines to `out`.
+pub fn run<L>(args: &Args, out: &mut impl Write) -> anyhow::Result<()>
+where
+    L: DataLoader,
+    L::Error: Into<anyhow::Error>,
+    <L::MessageIterator as MessageIterator>::Error: Into<anyhow::Error>,
+{
+    // Foxglove only gives loaders paths it can open, so loaders don't handle errors opening them
+    for path in &args.paths {
+        reader::check(path).with_context(|| format!("failed to open {path}"))?;
+    }
+    let paths: Vec<&str> = args.paths.iter().map(String::as_str).collect();
+    let mut harness = Harness::<L>::open(&paths).context("failed to initialize")?;
+    let info = harness.info().clone();
+    let channels = if args.channels.is_empty() {
+        info.channel_ids()
+    } else {
+        args.channels
+            .iter()
+            .map(|channel| find_channel(&info, channel))
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    writeln!(out, "{}", info_json(&info))?;
+    if args.info_only {
+        return Ok(());
+    }
+
+    if let Some(time) = args.backfill {
+        for message in harness.backfill(time, channels)? {
+            writeln!(out, "{}", message_json(&info, &message))?;
+        }
+        return Ok(());
+    }
+
+    let mut iter = harness
+        .loader()
+        .create_iter(MessageIteratorArgs {
+            start_time: args.start_time,
+            end_time: args.end_time,
+            channels,
+        })
+        .map_err(Into::into)?;
+    while let Some(message) = iter.next() {
+        let message = message.map_err(Into::into)?;
+        writeln!(out, "{}", message_json(&info, &message))?;
+    }
+    Ok(())
+}
+
+/// The id of the channel with `name` as its topic, or as its id.
+fn find_channel(info: &LoaderInfo, name: &str) -> anyhow::Result<u16> {
+    info.channels
+        .iter()
+        .find(|channel| channel.topic == name)
+        .or_else(|| {
+            let id: u16 = name.parse().ok()?;
+            info.channels.iter().find(|channel| channel.id == id)
+        })
+        .map(|channel| channel.id)
+        .ok_or_else(|| anyhow!("no channel {name}"))
+}
+
+fn info_json(info: &LoaderInfo) -> Value {
+    let channels: Vec<Value> = info
+        .channels
+        .iter()
+        .map(|channel| {
+            json!({
+                "id": channel.id,
+                "topic": channel.topic,
+                "message_encoding": channel.message_encoding,
+                "schema_id": channel.schema_id,
+                "message_count": channel.message_count,
+            })
+        })
+        .collect();
+    let schemas: Vec<Value> = info
+        .schemas
+        .iter()
+        .map(|schema| {
+            let mut value = json!({
+                "id": schema.id,
+                "name": schema.name,
+                "encoding": schema.encoding,
+            });
+            // Text schemas like JSON Schema are printed as they are
+            match std::str::from_utf8(&schema.data) {
+                Ok(text) => value["data"] = text.into(),
+                Err(_) => value["data_base64"] = BASE64_STANDARD.encode(&schema.data).into(),
+            }
+            value
+        })
+        .collect();
+    let problems: Vec<Value> = info
+        .problems
+        .iter()
+        .map(|problem| {
+            let severity = match problem.severity {
+                Severity::Info => "info",
+                Severity::Warn => "warn",
+                Severity::Error => "error",
+            };
+            json!({
+                "severity": severity,
+                "message": problem.message,
+                "tip": problem.tip,
+            })
+        })
+        .collect();
+    json!({
+        "start_time": info.start_time,
+        "end_time": info.end_time,
+        "channels": channels,
+        "schemas": schemas,
+        "problems": problems,
+    })
+}
+
+fn message_json(info: &LoaderInfo, message: &Message) -> Value {
+    let channel = info
+        .channels
+        .iter()
+        .find(|channel| channel.id == message.channel_id);
+    let mut value = json!({
+        "channel_id": message.channel_id,
+        "topic": channel.map(|channel| channel.topic.as_str()),
+        "log_time": message.log_time,
+        "publish_time": message.publish_time,
+    });
+    let data = channel
+        .filter(|channel| channel.message_encoding == "json")
+        .and_then(|_| serde_json::from_slice::<Value>(&message.data).ok());
+    match data {
+        Some(data) => value["data"] = data,
+        None => value["data_base64"] = BASE64_STANDARD.encode(&message.data).into(),
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{harness::ChannelInfo, test_loader::LineLoader};
+
+    fn args(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn info() -> LoaderInfo {
+        let channel = |id: u16, topic: &str, message_encoding: &str| ChannelInfo {
+            id,
+            topic: topic.into(),
+            message_encoding: message_encoding.into(),
+            schema_id: None,
+            schema_name: None,
+            message_count: None,
+        };
+        LoaderInfo {
+            start_time: 0,
+            end_time: 10,
+            channels: vec![channel(1, "/json", "json"), channel(2, "/cdr", "cdr")],
+            schemas: vec![],
+            problems: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            args(&[
+                "--start",
+                "10",
+                "a.csv",
+                "--channel",
+                "/x",
+                "--channel",
+                "3",
+                "b.csv"
+            ])
+            .unwrap(),
+            Args {
+                paths: vec!["a.csv".into(), "b.csv".into()],
+                start_time: Some(10),
+                channels: vec!["/x".into(), "3".into()],
+                ..Args::default()
+            }
+        );
+        assert!(args(&["--help"]).unwrap().help);
+        assert!(args(&[]).is_err());
+        assert!(args(&["a.csv", "--end"]).is_err());
+        assert!(args(&["a.csv", "--end", "1.5"]).is_err());
+        assert!(args(&["a.csv", "--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_find_channel() {
+        let info = info();
+        assert_eq!(find_channel(&info, "/cdr").unwrap(), 2);
+        assert_eq!(find_channel(&info, "1").unwrap(), 1);
+        assert!(find_channel(&info, "3").is_err());
+    }
+
+    #[test]
+    fn test_message_json() {
+        let info = info();
+        let message = |channel_id: u16, data: &[u8]| Message {
+            channel_id,
+            log_time: 5,
+            publish_time: 6,
+            data: data.to_vec(),
+        };
+        assert_eq!(
+            message_json(&info, &message(1, br#"{"value":1}"#)),
+            json!({
+                "channel_id": 1,
+                "topic": "/json",
+                "log_time": 5,
+                "publish_time": 6,
+                "data": {"value": 1},
+            })
+        );
+        assert_eq!(
+            message_json(&info, &message(2, &[0, 1, 2])),
+            json!({
+                "channel_id": 2,
+                "topic": "/cdr",
+                "log_time": 5,
+                "publish_time": 6,
+                "data_base64": "AAEC",
+            })
+        );
+    }
+
+    /// Run [`LineLoader`] with the arguments, returning each line of output.
+    fn run_lines(args: &[&str]) -> anyhow::Result<Vec<Value>> {
+        let mut out = vec![];
+        run::<LineLoader>(&self::args(args)?, &mut out)?;
+        Ok(String::from_utf8(out)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect())
+    }
+
+    fn log_times(lines: &[Value]) -> Vec<u64> {
+        lines[1..]
+            .iter()
+            .map(|line| line["log_time"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_run() {
+        reader::insert_file(
+            "run.lines",
+            "10 /a {\"n\":1}\n20 /b {\"n\":2}\n30 /a {\"n\":3}\n",
+        );
+
+        let lines = run_lines(&["run.lines"]).unwrap();
+        assert_eq!(
+            (&lines[0]["start_time"], &lines[0]["end_time"]),
+            (&json!(10), &json!(30))
+        );
+        assert_eq!(lines[0]["channels"][1]["topic"], "/b");
+        assert_eq!(lines[0]["channels"][1]["message_count"], 1);
+        assert_eq!(
+            lines[1],
+            json!({
+                "channel_id": 0,
+                "topic": "/a",
+                "log_time": 10,
+                "publish_time": 10,
+                "data": {"n": 1},
+            })
+        );
+        assert_eq!(log_times(&lines), vec![10, 20, 30]);
+
+        let lines = run_lines(&["--start", "15", "--channel", "/a", "run.lines"]).unwrap();
+        assert_eq!(log_times(&lines), vec![30]);
+        let lines = run_lines(&["--backfill", "25", "run.lines"]).unwrap();
+        assert_eq!(log_times(&lines), vec![10, 20]);
+        assert_eq!(run_lines(&["--info", "run.lines"]).unwrap().len(), 1);
+        let err = run_lines(&["--channel", "/c", "run.lines"]).unwrap_err();
+        assert_eq!(err.to_string(), "no channel /c");
+    }
+
+    #[test]
+    fn test_run_missing_file() {
+        let err = run_lines(&["missing.lines"]).unwrap_err();
+        assert_eq!(err.to_string(), "failed to open missing.lines");
+    }
+}
@@ -0,0 +1,184 @@
+//! A small loader for testing the runner and the conformance checks.
+//!
+//! Each line of its files is a message: a log time, a topic and the JSON message itself,
+//! separated by spaces. Each topic becomes a channel, numbered in the order the topics first
+//! appear. A `# fault <name>` line makes the loader break one of the rules checked by
+//! [`crate::conformance`], to test that the check catches it.
+
+use std::{collections::BTreeSet, io::Read};
+
+use anyhow::{Context, bail};
+use foxglove_data_loader::{
+    BackfillArgs, DataLoader, DataLoaderArgs, Initialization, Message, MessageIterator,
+    MessageIteratorArgs,
+};
+
+use crate::reader;
+
+/// A rule for the loader to break.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fault {
+    /// Emit messages in reverse log time order
+    Unordered,
+    /// Emit messages outside of the requested time range
+    IgnoreTimeRange,
+    /// Report a time range from `initialize` that ends before the last message
+    ShortTimeRange,
+    /// Emit messages on every channel, whichever were requested
+    IgnoreChannels,
+    /// Report one more message on each channel than there is
+    WrongMessageCount,
+    /// Backfill every message on the requested channels, even after the backfill time
+    BackfillEverything,
+}
+
+impl Fault {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "unordered" => Self::Unordered,
+            "ignore-time-range" => Self::IgnoreTimeRange,
+            "short-time-range" => Self::ShortTimeRange,
+            "ignore-channels" => Self::IgnoreChannels,
+            "wrong-message-count" => Self::WrongMessageCount,
+            "backfill-everything" => Self::BackfillEverything,
+            _ => bail!("unknown fault {name}"),
+        })
+    }
+}
+
+pub struct LineLoader {
+    paths: Vec<String>,
+    /// Every message in the files, by log time
+    messages: Vec<Message>,
+    fault: Option<Fault>,
+}
+
+impl DataLoader for LineLoader {
+    type MessageIterator = LineIterator;
+    type Error = anyhow::Error;
+
+    fn new(args: DataLoaderArgs) -> Self {
+        Self {
+            paths: args.paths,
+            messages: vec![],
+            fault: None,
+        }
+    }
+
+    fn initialize(&mut self) -> Result<Initialization, Self::Error> {
+        let mut topics: Vec<String> = vec![];
+        for path in &self.paths {
+            let mut text = String::new();
+            Read::read_to_string(&mut reader::open(path), &mut text)?;
+            for line in text.lines() {
+                if let Some(name) = line.strip_prefix("# fault ") {
+                    self.fault = Some(Fault::parse(name)?);
+                    continue;
+                }
+                let mut fields = line.splitn(3, ' ');
+                let (Some(log_time), Some(topic), Some(data)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    bail!("expected a log time, topic and message: {line:?}");
+                };
+                let log_time = log_time.parse().context("invalid log time")?;
+                let channel_id = match topics.iter().position(|t| t == topic) {
+                    Some(index) => index,
+                    None => {
+                        topics.push(topic.to_string());
+                        topics.len() - 1
+                    }
+                };
+                self.messages.push(Message {
+                    channel_id: channel_id as u16,
+                    log_time,
+                    publish_time: log_time,
+                    data: data.as_bytes().to_vec(),
+                });
+            }
+        }
+        self.messages.sort_by_key(|message| message.log_time);
+
+        let mut end_time = self.messages.last().map_or(0, |message| message.log_time);
+        if self.fault == Some(Fault::ShortTimeRange) {
+            end_time -= 1;
+        }
+        let mut init = Initialization::builder()
+            .start_time(self.messages.first().map_or(0, |message| message.log_time))
+            .end_time(end_time);
+        for (id, topic) in topics.iter().enumerate() {
+            let id = id as u16;
+            let mut count = self.messages.iter().filter(|m| m.channel_id == id).count();
+            if self.fault == Some(Fault::WrongMessageCount) {
+                count += 1;
+            }
+            init.add_channel_with_id(id, topic)
+                .expect("channel is free")
+                .message_encoding("json")
+                .message_count(count as u64);
+        }
+        Ok(init.build())
+    }
+
+    fn create_iter(
+        &mut self,
+        args: MessageIteratorArgs,
+    ) -> Result<Self::MessageIterator, Self::Error> {
+        let (start_time, end_time) = match self.fault {
+            Some(Fault::IgnoreTimeRange) => (0, u64::MAX),
+            _ => (
+                args.start_time.unwrap_or(0),
+                args.end_time.unwrap_or(u64::MAX),
+            ),
+        };
+        let mut messages: Vec<Message> = self
+            .messages
+            .iter()
+            .filter(|message| {
+                self.fault == Some(Fault::IgnoreChannels)
+                    || args.channels.contains(&message.channel_id)
+            })
+            .filter(|message| start_time <= message.log_time && message.log_time <= end_time)
+            .cloned()
+            .collect();
+        if self.fault == Some(Fault::Unordered) {
+            messages.reverse();
+        }
+        Ok(LineIterator {
+            messages: messages.into_iter(),
+        })
+    }
+
+    fn get_backfill(&mut self, args: BackfillArgs) -> Result<Vec<Message>, Self::Error> {
+        let channels: BTreeSet<u16> = args.channels.into_iter().collect();
+        if self.fault == Some(Fault::BackfillEverything) {
+            return Ok(self
+                .messages
+                .iter()
+                .filter(|message| channels.contains(&message.channel_id))
+                .cloned()
+                .collect());
+        }
+        Ok(channels
+            .into_iter()
+            .filter_map(|channel_id| {
+                self.messages
+                    .iter()
+                    .rfind(|m| m.channel_id == channel_id && m.log_time <= args.time)
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+pub struct LineIterator {
+    messages: std::vec::IntoIter<Message>,
+}
+
+impl MessageIterator for LineIterator {
+    type Error = anyhow::Error;
+
+    fn next(&mut self) -> Option<Result<Message, Self::Error>> {
+        self.messages.next().map(Ok)
+    }
+}
@@ -0,0 +1,262 @@
+//! An index from log time to where the data for that time is in a file, such as a byte offset or
+//! a row number.
+//!
+//! A loader builds its index once in `initialize`, then uses it to start each message iterator
+//! close to its start time instead of reading from the beginning of the file. The index can hold
+//! every record, or only checkpoints every so often, in which case the iterator reads forward from
+//! a checkpoint and skips the records before its start time.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Positions in a file ordered by log time. Several positions can have the same time, and they're
+/// kept in the order they were added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeIndex<P> {
+    entries: Vec<(u64, P)>,
+}
+
+impl<P> Default for TimeIndex<P> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<P> TimeIndex<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the position of a record at `time`. Adding records in time order is fastest, but they
+    /// can be added in any order. Each record added out of order moves the ones after it, so to
+    /// index many records that aren't in time order, collect them into the index instead.
+    pub fn insert(&mut self, time: u64, position: P) {
+        // Records at the same time go after the ones already added
+        let index = match self.entries.last() {
+            Some(&(last, _)) if last > time => self.entries.partition_point(|&(t, _)| t <= time),
+            _ => self.entries.len(),
+        };
+        self.entries.insert(index, (time, position));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The time of the first entry.
+    pub fn start_time(&self) -> Option<u64> {
+        self.entries.first().map(|&(time, _)| time)
+    }
+
+    /// The time of the last entry.
+    pub fn end_time(&self) -> Option<u64> {
+        self.entries.last().map(|&(time, _)| time)
+    }
+
+    /// Every entry in time order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (u64, &P)> + ExactSizeIterator {
+        self.entries
+            .iter()
+            .map(|(time, position)| (*time, position))
+    }
+
+    /// The position to start reading from to get every record at or after `time`.
+    ///
+    /// When the index only holds checkpoints, records at `time` can come before the first
+    /// checkpoint at `time`, so this is the last position before `time`, or the first position if
+    /// there isn't one. The reader has to skip the records before `time` itself.
+    pub fn seek(&self, time: u64) -> Option<&P> {
+        let before = self.entries.partition_point(|&(t, _)| t < time);
+        self.entries
+            .get(before.saturating_sub(1))
+            .map(|(_, position)| position)
+    }
+
+    /// The last entry at or before `time`. For data like audio frames, where each entry covers
+    /// the time until the next one, this is the entry covering `time`.
+    pub fn latest(&self, time: u64) -> Option<(u64, &P)> {
+        let count = self.entries.partition_point(|&(t, _)| t <= time);
+        let (time, position) = self.entries.get(count.checked_sub(1)?)?;
+        Some((*time, position))
+    }
+
+    /// The entries from `start_time` to `end_time` inclusive, like the time range of a message
+    /// iterator. A missing bound leaves that end of the range open.
+    pub fn range(
+        &self,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> impl DoubleEndedIterator<Item = (u64, &P)> + ExactSizeIterator {
+        let start = start_time.map_or(0, |start| self.entries.partition_point(|&(t, _)| t < start));
+        let end = end_time.map_or(self.entries.len(), |end| {
+            self.entries.partition_point(|&(t, _)| t <= end)
+        });
+        self.entries[start..end.max(start)]
+            .iter()
+            .map(|(time, position)| (*time, position))
+    }
+}
+
+/// Collecting entries into an index sorts them once, keeping entries with the same time in the
+/// order they came in.
+impl<P> FromIterator<(u64, P)> for TimeIndex<P> {
+    fn from_iter<I: IntoIterator<Item = (u64, P)>>(iter: I) -> Self {
+        let mut index = Self::new();
+        index.extend(iter);
+        index
+    }
+}
+
+impl<P> Extend<(u64, P)> for TimeIndex<P> {
+    fn extend<I: IntoIterator<Item = (u64, P)>>(&mut self, iter: I) {
+        self.entries.extend(iter);
+        // The sort is stable, and takes linear time when the entries are already in order
+        self.entries.sort_by_key(|&(time, _)| time);
+    }
+}
+
+/// A [`TimeIndex`] for each channel, to find the latest message on each channel for backfill.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelTimeIndex<P> {
+    channels: BTreeMap<u16, TimeIndex<P>>,
+}
+
+impl<P> Default for ChannelTimeIndex<P> {
+    fn default() -> Self {
+        Self {
+            channels: BTreeMap::new(),
+        }
+    }
+}
+
+impl<P> ChannelTimeIndex<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the position of a message on `channel_id` at `time`.
+    pub fn insert(&mut self, channel_id: u16, time: u64, position: P) {
+        self.channels
+            .entry(channel_id)
+            .or_default()
+            .insert(time, position);
+    }
+
+    /// The index of one channel, if it has any entries.
+    pub fn channel(&self, channel_id: u16) -> Option<&TimeIndex<P>> {
+        self.channels.get(&channel_id)
+    }
+
+    /// The number of entries on a channel.
+    pub fn message_count(&self, channel_id: u16) -> usize {
+        self.channel(channel_id).map_or(0, TimeIndex::len)
+    }
+
+    /// The last entry at or before `time` on each of `channels`, in channel order. Channels with
+    /// nothing by then are left out, so there's at most one entry per channel.
+    pub fn latest(
+        &self,
+        time: u64,
+        channels: impl IntoIterator<Item = u16>,
+    ) -> Vec<(u16, u64, &P)> {
+        let channels: BTreeSet<u16> = channels.into_iter().collect();
+        channels
+            .into_iter()
+            .filter_map(|channel_id| {
+                let (time, position) = self.channel(channel_id)?.latest(time)?;
+                Some((channel_id, time, position))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(entries: &[(u64, u32)]) -> TimeIndex<u32> {
+        let mut index = TimeIndex::new();
+        for &(time, position) in entries {
+            index.insert(time, position);
+        }
+        index
+    }
+
+    fn positions<'a>(entries: impl Iterator<Item = (u64, &'a u32)>) -> Vec<u32> {
+        entries.map(|(_, position)| *position).collect()
+    }
+
+    #[test]
+    fn test_insert_keeps_duplicates_in_order() {
+        let index = index(&[(20, 3), (10, 1), (20, 4), (10, 2), (5, 0)]);
+        assert_eq!(positions(index.iter()), vec![0, 1, 2, 3, 4]);
+        assert_eq!(index.len(), 5);
+        assert_eq!((index.start_time(), index.end_time()), (Some(5), Some(20)));
+    }
+
+    #[test]
+    fn test_collect_keeps_duplicates_in_order() {
+        let entries = [(20, 3), (10, 1), (20, 4), (10, 2), (5, 0)];
+        let collected: TimeIndex<u32> = entries.into_iter().collect();
+        assert_eq!(collected, index(&entries));
+
+        let mut extended = index(&[(10, 0), (30, 3)]);
+        extended.extend([(30, 4), (20, 2), (10, 1)]);
+        assert_eq!(positions(extended.iter()), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let index = index(&[(10, 0), (20, 1), (20, 2), (30, 3)]);
+        assert_eq!(index.seek(0), Some(&0));
+        assert_eq!(index.seek(10), Some(&0));
+        // The records at 20 can start anywhere after the checkpoint at 10
+        assert_eq!(index.seek(20), Some(&0));
+        assert_eq!(index.seek(25), Some(&2));
+        assert_eq!(index.seek(100), Some(&3));
+        assert_eq!(TimeIndex::<u32>::new().seek(10), None);
+    }
+
+    #[test]
+    fn test_latest() {
+        let index = index(&[(10, 0), (20, 1), (20, 2), (30, 3)]);
+        assert_eq!(index.latest(5), None);
+        assert_eq!(index.latest(10), Some((10, &0)));
+        assert_eq!(index.latest(20), Some((20, &2)));
+        assert_eq!(index.latest(29), Some((20, &2)));
+        assert_eq!(index.latest(u64::MAX), Some((30, &3)));
+    }
+
+    #[test]
+    fn test_range() {
+        let index = index(&[(10, 0), (20, 1), (20, 2), (30, 3)]);
+        assert_eq!(positions(index.range(None, None)), vec![0, 1, 2, 3]);
+        assert_eq!(positions(index.range(Some(20), Some(20))), vec![1, 2]);
+        assert_eq!(positions(index.range(Some(11), None)), vec![1, 2, 3]);
+        assert_eq!(positions(index.range(None, Some(19))), vec![0]);
+        assert_eq!(index.range(Some(30), Some(10)).len(), 0);
+        assert_eq!(index.range(Some(31), None).len(), 0);
+    }
+
+    #[test]
+    fn test_channel_latest() {
+        let mut index = ChannelTimeIndex::new();
+        index.insert(2, 10, 0);
+        index.insert(1, 15, 1);
+        index.insert(2, 20, 2);
+        index.insert(1, 20, 3);
+        assert_eq!(index.message_count(2), 2);
+        assert_eq!(index.message_count(9), 0);
+        assert_eq!(
+            index.latest(15, [2, 1, 2, 9]),
+            vec![(1, 15, &1), (2, 10, &0)]
+        );
+        assert_eq!(index.latest(5, [1, 2]), vec![]);
+        assert_eq!(index.latest(20, [1]), vec![(1, 20, &3)]);
+    }
+}
@@ -0,0 +1,527 @@
+//! Reading of FLAC files.
+//!
+//! A FLAC file starts with metadata blocks: the stream info with the sample format and length,
+//! and optionally a seek table, Vorbis comments and pictures. The audio follows as a sequence of
+//! frames, each starting with a header holding the position of its first sample.
+//!
+//! The seek table gives the byte offsets of frames throughout the file, so when it's present the
+//! file doesn't need to be scanned. Otherwise the frame headers are found by searching the audio
+//! for their sync code and checking their CRC. The frames themselves are decoded with `claxon`.
+
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::{
+    AudioDecoder, FrameFormat,
+    frame::id3v2_len,
+    id3::{Id3Tag, Picture},
+};
+
+const STREAMINFO: u8 = 0;
+const SEEKTABLE: u8 = 3;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+/// Seek table entries with this sample number are placeholders
+const PLACEHOLDER_POINT: u64 = u64::MAX;
+
+/// The longest possible frame header: 4 fixed bytes, a 7 byte frame number, 2 bytes each of
+/// block size and sample rate, and the CRC.
+const MAX_HEADER_LEN: usize = 16;
+
+/// The number of bytes read at a time when scanning for frames
+const SCAN_BUFFER_LEN: usize = 64 * 1024;
+
+/// The metadata at the start of a FLAC file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlacInfo {
+    pub stream: StreamInfo,
+    /// The first sample and byte offset of frames from the seek table
+    pub seek_points: Vec<(u64, u64)>,
+    /// Tags from the Vorbis comment and picture blocks
+    pub tag: Id3Tag,
+    /// The byte offset of the first frame
+    pub audio_start: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamInfo {
+    /// The largest number of samples per channel in a frame. All frames but the last have this
+    /// many samples in a stream with a fixed block size.
+    pub max_block_size: u16,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    /// The number of samples per channel in the stream, 0 if unknown
+    pub total_samples: u64,
+}
+
+impl FlacInfo {
+    /// Read the metadata blocks from the start of a FLAC file. A leading ID3v2 tag is skipped.
+    pub fn read(reader: impl Read + Seek) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut offset = id3v2_len(reader.fill_buf()?);
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != b"fLaC" {
+            return Err(invalid_data("missing fLaC marker"));
+        }
+        offset += 4;
+
+        let mut stream = None;
+        let mut seek_points = vec![];
+        let mut tag = Id3Tag::default();
+        loop {
+            let mut header = [0u8; 4];
+            reader.read_exact(&mut header)?;
+            let last = header[0] & 0x80 != 0;
+            let block_type = header[0] & 0x7f;
+            let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body)?;
+            offset += 4 + len as u64;
+            match block_type {
+                STREAMINFO => stream = Some(StreamInfo::parse(&body)?),
+                SEEKTABLE => {
+                    seek_points = body
+                        .chunks_exact(18)
+                        .map(|point| {
+                            let sample = u64::from_be_bytes(point[..8].try_into().unwrap());
+                            let offset = u64::from_be_bytes(point[8..16].try_into().unwrap());
+                            (sample, offset)
+                        })
+                        .filter(|&(sample, _)| sample != PLACEHOLDER_POINT)
+                        .collect();
+                }
+                VORBIS_COMMENT => {
+                    if let Some(comments) = Id3Tag::parse_vorbis_comment(&body) {
+                        tag.merge(comments);
+                    }
+                }
+                PICTURE => {
+                    if let Some(picture) = parse_picture(&body) {
+                        tag.picture.get_or_insert(picture);
+                    }
+                }
+                _ => (),
+            }
+            if last {
+                break;
+            }
+        }
+        let stream = stream.ok_or_else(|| invalid_data("missing stream info"))?;
+        // Seek table offsets are relative to the first frame
+        for (_, point_offset) in &mut seek_points {
+            *point_offset += offset;
+        }
+        Ok(Self {
+            stream,
+            seek_points,
+            tag,
+            audio_start: offset,
+        })
+    }
+}
+
+impl StreamInfo {
+    fn parse(body: &[u8]) -> io::Result<Self> {
+        if body.len() < 18 {
+            return Err(invalid_data("stream info too short"));
+        }
+        // After the block and frame sizes come 20 bits of sample rate, 3 bits of channels - 1,
+        // 5 bits of bits per sample - 1 and 36 bits of total samples.
+        let packed = u64::from_be_bytes(body[10..18].try_into().unwrap());
+        let info = Self {
+            max_block_size: u16::from_be_bytes([body[2], body[3]]),
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0x7) as u8 + 1,
+            bits_per_sample: ((packed >> 36) & 0x1f) as u8 + 1,
+            total_samples: packed & 0xf_ffff_ffff,
+        };
+        if info.sample_rate == 0 || info.max_block_size == 0 {
+            return Err(invalid_data("invalid stream info"));
+        }
+        Ok(info)
+    }
+}
+
+/// Parse a picture block into the picture type, MIME type and image data.
+fn parse_picture(body: &[u8]) -> Option<Picture> {
+    let u32_at = |pos: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(body.get(pos..pos + 4)?.try_into().unwrap()) as usize)
+    };
+    let picture_type = u32_at(0)?;
+    let mime_len = u32_at(4)?;
+    let mime_type = body.get(8..8 + mime_len)?;
+    let description_len = u32_at(8 + mime_len)?;
+    // The description is followed by the width, height, color depth and palette size
+    let data_pos = 8 + mime_len + 4 + description_len + 16;
+    let data_len = u32_at(data_pos)?;
+    let data = body.get(data_pos + 4..data_pos + 4 + data_len)?;
+    Some(Picture {
+        mime_type: String::from_utf8_lossy(mime_type).into_owned(),
+        picture_type: picture_type as u8,
+        data: data.to_vec(),
+    })
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid FLAC file: {msg}"),
+    )
+}
+
+/// The fields of a frame header needed to index the frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameHeader {
+    /// The number of the first sample in the frame, counting samples per channel
+    first_sample: u64,
+    block_size: u32,
+}
+
+impl FrameHeader {
+    /// Parse the frame header at the start of `buf`, returning None if it isn't a valid header.
+    /// Frames in a stream with a fixed block size are numbered by frame rather than by sample, so
+    /// the stream's block size is needed to find the first sample.
+    fn parse(buf: &[u8], stream: &StreamInfo) -> Option<Self> {
+        let &[0xff, sync @ (0xf8 | 0xf9), sizes, format, ..] = buf else {
+            return None;
+        };
+        let variable_block_size = sync == 0xf9;
+        let (block_size_code, sample_rate_code) = (sizes >> 4, sizes & 0xf);
+        let (channels_code, sample_size_code) = (format >> 4, (format >> 1) & 0x7);
+        if block_size_code == 0
+            || sample_rate_code == 0xf
+            || channels_code > 10
+            || sample_size_code == 3
+            || format & 1 != 0
+        {
+            return None;
+        }
+
+        let mut pos = 4;
+        // The frame or sample number is coded like UTF-8, extended up to 7 bytes
+        let first = *buf.get(pos)?;
+        let extra = match first.leading_ones() {
+            0 => 0,
+            ones @ 2..=7 => ones as usize - 1,
+            _ => return None,
+        };
+        let mut number = (first & (0x7f >> extra)) as u64;
+        for &byte in buf.get(pos + 1..pos + 1 + extra)? {
+            if byte & 0xc0 != 0x80 {
+                return None;
+            }
+            number = (number << 6) | (byte & 0x3f) as u64;
+        }
+        pos += 1 + extra;
+
+        let block_size = match block_size_code {
+            1 => 192,
+            2..=5 => 576 << (block_size_code - 2),
+            6 => {
+                pos += 1;
+                *buf.get(pos - 1)? as u32 + 1
+            }
+            7 => {
+                pos += 2;
+                u16::from_be_bytes(buf.get(pos - 2..pos)?.try_into().unwrap()) as u32 + 1
+            }
+            _ => 256 << (block_size_code - 8),
+        };
+        pos += match sample_rate_code {
+            12 => 1,
+            13 | 14 => 2,
+            _ => 0,
+        };
+        let crc = *buf.get(pos)?;
+        if crc8(&buf[..pos]) != crc {
+            return None;
+        }
+        let first_sample = if variable_block_size {
+            number
+        } else {
+            number * stream.max_block_size as u64
+        };
+        Some(Self {
+            first_sample,
+            block_size,
+        })
+    }
+}
+
+/// The CRC-8 of a frame header, with polynomial `x^8 + x^2 + x + 1`.
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Find the frames of the audio by searching for their headers, returning the first sample and
+/// byte offset of each frame.
+///
+/// Audio data can contain a valid looking header by chance, so a header is only accepted if its
+/// first sample follows on from the previous frame. A small gap is allowed so the scan resumes
+/// after a damaged frame.
+pub fn scan_frames(mut reader: impl Read + Seek, info: &FlacInfo) -> io::Result<Vec<(u64, u64)>> {
+    reader.seek(SeekFrom::Start(info.audio_start))?;
+    let max_gap = 16 * info.stream.max_block_size as u64;
+    let mut frames = vec![];
+    let mut next_sample = 0;
+    let mut buf = Vec::new();
+    // The byte offset of the start of `buf`
+    let mut offset = info.audio_start;
+    loop {
+        let len = buf.len();
+        buf.resize(len + SCAN_BUFFER_LEN, 0);
+        let read = reader.read(&mut buf[len..])?;
+        buf.truncate(len + read);
+        let eof = read == 0;
+        // Leave the bytes that could be the start of a header cut off by the end of the buffer
+        // until more has been read
+        let end = if eof {
+            buf.len()
+        } else {
+            buf.len().saturating_sub(MAX_HEADER_LEN)
+        };
+        for pos in 0..end {
+            if buf[pos] != 0xff {
+                continue;
+            }
+            let Some(header) = FrameHeader::parse(&buf[pos..], &info.stream) else {
+                continue;
+            };
+            if (next_sample..=next_sample + max_gap).contains(&header.first_sample) {
+                frames.push((header.first_sample, offset + pos as u64));
+                next_sample = header.first_sample + header.block_size as u64;
+            }
+        }
+        if eof {
+            return Ok(frames);
+        }
+        buf.drain(..end);
+        offset += end as u64;
+    }
+}
+
+/// Decodes the frames of a FLAC stream.
+pub struct FlacDecoder<R: Read> {
+    frames: claxon::frame::FrameReader<claxon::input::BufferedReader<R>>,
+    sample_rate: u32,
+    /// Scales samples to the range -1 to 1
+    scale: f32,
+    /// The buffer of the last block, reused for the next one
+    buffer: Vec<i32>,
+}
+
+impl<R: Read> FlacDecoder<R> {
+    /// Create a decoder reading from `reader`, which should be positioned at the start of a frame.
+    pub fn new(reader: R, stream: &StreamInfo) -> Self {
+        let input = claxon::input::BufferedReader::new(reader);
+        Self {
+            frames: claxon::frame::FrameReader::new(input),
+            sample_rate: stream.sample_rate,
+            scale: 1.0 / (1u64 << (stream.bits_per_sample - 1)) as f32,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> AudioDecoder for FlacDecoder<R> {
+    fn decode(&mut self, samples: &mut Vec<f32>) -> anyhow::Result<Option<FrameFormat>> {
+        let buffer = std::mem::take(&mut self.buffer);
+        let Some(block) = self.frames.read_next_or_eof(buffer)? else {
+            return Ok(None);
+        };
+        let channels = block.channels();
+        samples.clear();
+        for i in 0..block.duration() {
+            for channel in 0..channels {
+                samples.push(block.sample(channel, i) as f32 * self.scale);
+            }
+        }
+        self.buffer = block.into_buffer();
+        Ok(Some(FrameFormat {
+            channels: channels as usize,
+            sample_rate: self.sample_rate,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const STREAM: StreamInfo = StreamInfo {
+        max_block_size: 256,
+        sample_rate: 48_000,
+        channels: 1,
+        bits_per_sample: 16,
+        total_samples: 5 * 256,
+    };
+
+    fn stream_info_block(stream: &StreamInfo) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend(stream.max_block_size.to_be_bytes());
+        body.extend(stream.max_block_size.to_be_bytes());
+        body.extend([0; 6]); // frame sizes
+        let packed = (stream.sample_rate as u64) << 44
+            | (stream.channels as u64 - 1) << 41
+            | (stream.bits_per_sample as u64 - 1) << 36
+            | stream.total_samples;
+        body.extend(packed.to_be_bytes());
+        body.extend([0; 16]); // MD5
+        body
+    }
+
+    /// Build a file from metadata blocks and audio data.
+    fn flac(blocks: &[(u8, Vec<u8>)], audio: &[u8]) -> Vec<u8> {
+        let mut file = b"fLaC".to_vec();
+        for (i, (block_type, body)) in blocks.iter().enumerate() {
+            let last = if i == blocks.len() - 1 { 0x80 } else { 0 };
+            file.push(last | block_type);
+            file.extend(&(body.len() as u32).to_be_bytes()[1..]);
+            file.extend(body);
+        }
+        file.extend(audio);
+        file
+    }
+
+    /// The header of a mono, 16 bit, 48 kHz frame of 256 samples.
+    fn frame_header(number: u8) -> Vec<u8> {
+        let mut header = vec![0xff, 0xf8, 0x8a, 0x08, number];
+        header.push(crc8(&header));
+        header
+    }
+
+    /// A frame where every sample has the same value, using a constant subframe.
+    fn constant_frame(number: u8, value: i16) -> Vec<u8> {
+        let mut frame = frame_header(number);
+        frame.push(0x00);
+        frame.extend(value.to_be_bytes());
+        let crc = frame.iter().fold(0u16, |crc, &byte| {
+            (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| {
+                if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                }
+            })
+        });
+        frame.extend(crc.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_read_metadata() {
+        let mut seek_table = vec![];
+        for (sample, offset) in [(0u64, 0u64), (512, 100), (PLACEHOLDER_POINT, 0)] {
+            seek_table.extend(sample.to_be_bytes());
+            seek_table.extend(offset.to_be_bytes());
+            seek_table.extend(256u16.to_be_bytes());
+        }
+        let mut comments = vec![];
+        for text in ["vendor", "TITLE=Engine run 3"] {
+            comments.extend((text.len() as u32).to_le_bytes());
+            comments.extend(text.as_bytes());
+            if text == "vendor" {
+                comments.extend(1u32.to_le_bytes());
+            }
+        }
+        let mut picture = vec![0, 0, 0, 3];
+        picture.extend(9u32.to_be_bytes());
+        picture.extend(b"image/png");
+        picture.extend(0u32.to_be_bytes());
+        picture.extend([0; 16]);
+        picture.extend(3u32.to_be_bytes());
+        picture.extend([1, 2, 3]);
+
+        let file = flac(
+            &[
+                (STREAMINFO, stream_info_block(&STREAM)),
+                (SEEKTABLE, seek_table),
+                (VORBIS_COMMENT, comments),
+                (PICTURE, picture),
+            ],
+            &[],
+        );
+        let info = FlacInfo::read(Cursor::new(&file)).unwrap();
+        assert_eq!(info.stream, STREAM);
+        assert_eq!(info.audio_start, file.len() as u64);
+        let start = info.audio_start;
+        assert_eq!(info.seek_points, vec![(0, start), (512, start + 100)]);
+        assert_eq!(info.tag.title.as_deref(), Some("Engine run 3"));
+        let picture = info.tag.picture.unwrap();
+        assert_eq!(
+            (picture.format(), picture.data),
+            (Some("png"), vec![1, 2, 3])
+        );
+
+        assert!(FlacInfo::read(Cursor::new(b"OggS\x00\x00")).is_err());
+    }
+
+    #[test]
+    fn test_parse_frame_header() {
+        let header = FrameHeader::parse(&frame_header(3), &STREAM).unwrap();
+        assert_eq!(header.first_sample, 3 * 256);
+        assert_eq!(header.block_size, 256);
+
+        let mut bad_crc = frame_header(3);
+        bad_crc[5] ^= 1;
+        assert_eq!(FrameHeader::parse(&bad_crc, &STREAM), None);
+
+        // A variable block size frame of 1000 samples, numbered by sample with a 2 byte number
+        // and the block size in a 16 bit field
+        let mut header = vec![0xff, 0xf9, 0x7a, 0x08, 0xc4, 0x80, 0x03, 0xe7];
+        header.push(crc8(&header));
+        let header = FrameHeader::parse(&header, &STREAM).unwrap();
+        assert_eq!(header.first_sample, 256);
+        assert_eq!(header.block_size, 1000);
+    }
+
+    #[test]
+    fn test_scan_frames() {
+        let mut audio = vec![];
+        let mut offsets = vec![];
+        for number in 0..5 {
+            offsets.push(audio.len() as u64);
+            audio.extend(constant_frame(number, 1000));
+            // Data that looks like a header, but of a frame far from this one
+            audio.extend(frame_header(number + 40));
+        }
+        let file = flac(&[(STREAMINFO, stream_info_block(&STREAM))], &audio);
+        let info = FlacInfo::read(Cursor::new(&file)).unwrap();
+        let frames = scan_frames(Cursor::new(&file), &info).unwrap();
+        let expected: Vec<(u64, u64)> = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, offset)| (i as u64 * 256, info.audio_start + offset))
+            .collect();
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn test_decode_frames() {
+        let audio: Vec<u8> = (0..3)
+            .flat_map(|number| constant_frame(number, 8192 * number as i16))
+            .collect();
+        let mut decoder = FlacDecoder::new(Cursor::new(audio), &STREAM);
+        let mut samples = vec![];
+        for number in 0..3 {
+            let format = decoder.decode(&mut samples).unwrap().unwrap();
+            assert_eq!((format.channels, format.sample_rate), (1, 48_000));
+            assert_eq!(samples, vec![0.25 * number as f32; 256]);
+        }
+        assert!(decoder.decode(&mut samples).unwrap().is_none());
+    }
+}
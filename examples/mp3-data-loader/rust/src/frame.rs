@@ -0,0 +1,960 @@
+//! Parsing of MPEG audio frame headers.
+//!
+//! Each MPEG audio frame starts with a 4 byte header describing its bitrate, sample rate and
+//! channel layout. That is enough to know the length and duration of the frame, so the file can be
+//! indexed by reading only the headers and skipping over the audio data.
+//!
+//! Encoders usually write an info frame (a Xing, Info or VBRI tag) at the start of the file
+//! holding the total frame count. When it marks the file as constant bitrate (an Info tag), the
+//! file doesn't need to be scanned at all. Variable bitrate files are still scanned, as that's the
+//! only way to know exactly when each frame starts.
+//!
+//! Files can contain junk between frames, or be cut off in the middle of a frame. Both the scanner
+//! and the decoder skip over data they can't read, always moving forward, and count how much was
+//! skipped.
+
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::{AudioDecoder, FrameFormat, NS_PER_S};
+
+/// The length of the ID3v1 tag that can follow the audio data
+const ID3V1_LEN: u64 = crate::id3::ID3V1_LEN as u64;
+
+/// Length of an MPEG audio frame header in bytes
+pub const HEADER_LEN: usize = 4;
+
+/// Bitrates in kbit/s, indexed by the bitrate index of the header.
+const BITRATES_V1_L1: [u32; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const BITRATES_V1_L2: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const BITRATES_V1_L3: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const BITRATES_V2_L1: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const BITRATES_V2_L2_L3: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Layer1,
+    Layer2,
+    Layer3,
+}
+
+/// A parsed MPEG audio frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: Version,
+    pub layer: Layer,
+    /// Bitrate in bits per second
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channels: u8,
+    /// Whether a 16 bit CRC follows the header
+    pub crc: bool,
+}
+
+impl FrameHeader {
+    /// Parse a frame header, returning None if the bytes aren't a valid header.
+    ///
+    /// Free-format bitrates are not supported since the frame length can't be known from the
+    /// header alone.
+    pub fn parse(bytes: [u8; HEADER_LEN]) -> Option<Self> {
+        // 11 bits of frame sync
+        if bytes[0] != 0xff || bytes[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = match (bytes[1] >> 3) & 0b11 {
+            0 => Version::Mpeg25,
+            2 => Version::Mpeg2,
+            3 => Version::Mpeg1,
+            _ => return None,
+        };
+        let layer = match (bytes[1] >> 1) & 0b11 {
+            1 => Layer::Layer3,
+            2 => Layer::Layer2,
+            3 => Layer::Layer1,
+            _ => return None,
+        };
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 {
+            return None;
+        }
+        let bitrates = match (version, layer) {
+            (Version::Mpeg1, Layer::Layer1) => &BITRATES_V1_L1,
+            (Version::Mpeg1, Layer::Layer2) => &BITRATES_V1_L2,
+            (Version::Mpeg1, Layer::Layer3) => &BITRATES_V1_L3,
+            (_, Layer::Layer1) => &BITRATES_V2_L1,
+            (_, _) => &BITRATES_V2_L2_L3,
+        };
+        let base_sample_rate = match (bytes[2] >> 2) & 0b11 {
+            0 => 44_100,
+            1 => 48_000,
+            2 => 32_000,
+            _ => return None,
+        };
+        let sample_rate = match version {
+            Version::Mpeg1 => base_sample_rate,
+            Version::Mpeg2 => base_sample_rate / 2,
+            Version::Mpeg25 => base_sample_rate / 4,
+        };
+        Some(Self {
+            version,
+            layer,
+            bitrate: bitrates[bitrate_index] * 1000,
+            sample_rate,
+            padding: (bytes[2] >> 1) & 1 == 1,
+            channels: if bytes[3] >> 6 == 0b11 { 1 } else { 2 },
+            crc: bytes[1] & 1 == 0,
+        })
+    }
+
+    /// Whether a frame with this header can follow `previous` in the same stream.
+    pub fn follows(&self, previous: &FrameHeader) -> bool {
+        self.version == previous.version
+            && self.layer == previous.layer
+            && self.sample_rate == previous.sample_rate
+    }
+
+    /// The number of samples per channel in the frame.
+    pub fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (Layer::Layer1, _) => 384,
+            (Layer::Layer2, _) | (Layer::Layer3, Version::Mpeg1) => 1152,
+            (Layer::Layer3, _) => 576,
+        }
+    }
+
+    /// The length of the frame in bytes, including the header.
+    pub fn frame_len(&self) -> usize {
+        let (slot_len, padding) = match self.layer {
+            Layer::Layer1 => (4, self.padding as u32 * 4),
+            _ => (1, self.padding as u32),
+        };
+        let slots = self.samples() / 8 / slot_len * self.bitrate / self.sample_rate;
+        (slots * slot_len + padding) as usize
+    }
+
+    /// The duration of the frame in nanoseconds.
+    pub fn duration_ns(&self) -> u64 {
+        self.samples() as u64 * NS_PER_S / self.sample_rate as u64
+    }
+
+    /// The length of the Layer III side information that follows the header (and CRC).
+    fn side_info_len(&self) -> usize {
+        match (self.version, self.channels) {
+            (Version::Mpeg1, 1) => 17,
+            (Version::Mpeg1, _) => 32,
+            (_, 1) => 9,
+            (_, _) => 17,
+        }
+    }
+}
+
+/// The contents of a Xing, Info or VBRI tag from the first frame of a file.
+///
+/// The frame holding the tag contains no audio. LAME and FFmpeg extend the Xing tag with encoder
+/// details, which are skipped along with the rest of the frame.
+///
+/// Xing and VBRI tags also hold seek tables, which aren't read. They give rough byte offsets at
+/// fractions of the duration rather than the index of a frame, so the time of the frame found
+/// there isn't known exactly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfoTag {
+    /// The number of audio frames, not counting the frame holding the tag
+    pub frames: Option<u32>,
+    /// The length of the audio stream in bytes, starting from the frame holding the tag
+    pub bytes: Option<u32>,
+    /// Whether the stream has a constant bitrate, which encoders mark with an Info tag
+    pub constant_bitrate: bool,
+}
+
+impl InfoTag {
+    /// Parse a tag from the full contents of a frame, returning None if it doesn't hold one.
+    pub fn parse(header: &FrameHeader, frame: &[u8]) -> Option<Self> {
+        if header.layer != Layer::Layer3 {
+            return None;
+        }
+        let crc_len = if header.crc { 2 } else { 0 };
+        let xing = HEADER_LEN + crc_len + header.side_info_len();
+        match frame.get(xing..xing + 4)? {
+            b"Xing" => Self::parse_xing(&frame[xing + 4..], false),
+            b"Info" => Self::parse_xing(&frame[xing + 4..], true),
+            // The VBRI tag is always 32 bytes after the header
+            _ if frame.get(36..40)? == b"VBRI" => Self::parse_vbri(&frame[40..]),
+            _ => None,
+        }
+    }
+
+    fn parse_xing(tag: &[u8], constant_bitrate: bool) -> Option<Self> {
+        let flags = read_u32(tag, 0)?;
+        let mut pos = 4;
+        let mut field = |flag: u32, len: usize| {
+            let present = flags & flag != 0;
+            let start = pos;
+            if present {
+                pos += len;
+            }
+            present.then(|| tag.get(start..start + len)).flatten()
+        };
+        let frames = field(0x1, 4).and_then(|b| read_u32(b, 0));
+        let bytes = field(0x2, 4).and_then(|b| read_u32(b, 0));
+        Some(Self {
+            frames,
+            bytes,
+            constant_bitrate,
+        })
+    }
+
+    fn parse_vbri(tag: &[u8]) -> Option<Self> {
+        // version (2), delay (2) and quality (2) come before the stream length
+        let bytes = read_u32(tag, 6)?;
+        let frames = read_u32(tag, 10)?;
+        if frames == 0 || bytes == 0 {
+            return None;
+        }
+        Some(Self {
+            frames: Some(frames),
+            bytes: Some(bytes),
+            constant_bitrate: false,
+        })
+    }
+}
+
+/// The layout of a constant bitrate stream, from its info tag.
+///
+/// Padding makes the frames of a stream differ in length by a byte, but in a constant bitrate
+/// stream they average out to a fixed length, so the offset of any frame can be estimated to within
+/// a byte without scanning the frames before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConstantBitrate {
+    /// The byte offset of the first audio frame
+    pub first_frame: u64,
+    /// The average length of a frame in bytes
+    pub frame_len: f64,
+}
+
+impl ConstantBitrate {
+    /// Find the first frame at or after the frame at `index`, returning its index and byte offset.
+    ///
+    /// The stream is scanned for a frame header from just before the estimated offset of the
+    /// frame. The index of the frame found is worked out from its offset, so it's exact even if
+    /// the scan lands on a later frame.
+    pub fn find_frame<R: Read + Seek>(
+        &self,
+        reader: R,
+        end: u64,
+        index: u64,
+    ) -> io::Result<Option<(u64, u64)>> {
+        let estimate = self.first_frame as f64 + index as f64 * self.frame_len;
+        let from = (estimate as u64).saturating_sub(2).max(self.first_frame);
+        let mut scanner = FrameScanner::starting_at(reader, from, end)?;
+        Ok(scanner.next_frame()?.map(|(offset, _)| {
+            let index = ((offset - self.first_frame) as f64 / self.frame_len).round() as u64;
+            (index, offset)
+        }))
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+/// Reads the frame headers of an MPEG audio stream, skipping over the audio data.
+pub struct FrameScanner<R> {
+    reader: BufReader<R>,
+    position: u64,
+    /// The end of the audio data, before any ID3v1 tag
+    end: u64,
+    /// Whether the last frame was directly followed by the current position
+    in_sync: bool,
+    skipped_bytes: u64,
+    truncated_frames: u64,
+}
+
+impl<R: Read + Seek> FrameScanner<R> {
+    /// Create a scanner at the start of the stream, skipping a leading ID3v2 tag if present.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let end = audio_end(&mut reader)?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut scanner = Self {
+            reader: BufReader::new(reader),
+            position: 0,
+            end,
+            in_sync: true,
+            skipped_bytes: 0,
+            truncated_frames: 0,
+        };
+        // A tag running past the end of the stream has a corrupt size, so the audio is looked for
+        // from the start instead
+        let tag_len = id3v2_len(scanner.reader.fill_buf()?);
+        if tag_len <= end {
+            scanner.skip(tag_len)?;
+        }
+        Ok(scanner)
+    }
+
+    /// Create a scanner at `position`, which may be in the middle of a frame, stopping at `end`.
+    pub fn starting_at(mut reader: R, position: u64, end: u64) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(position))?;
+        Ok(Self {
+            reader: BufReader::new(reader),
+            position,
+            end,
+            in_sync: false,
+            skipped_bytes: 0,
+            truncated_frames: 0,
+        })
+    }
+
+    /// The byte offset of the end of the audio data. An ID3v1 tag at the end of the stream isn't
+    /// part of the audio.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// The number of bytes skipped because they weren't part of a valid frame.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// The number of frames skipped because they run past the end of the stream.
+    pub fn truncated_frames(&self) -> u64 {
+        self.truncated_frames
+    }
+
+    /// Read the info tag from the first frame if it has one.
+    ///
+    /// This returns the byte offset and header of the frame holding the tag, and moves past that
+    /// frame. If the first frame doesn't hold a tag the scanner is left where it was.
+    pub fn read_info_tag(&mut self) -> io::Result<Option<(u64, FrameHeader, InfoTag)>> {
+        let start = (
+            self.position,
+            self.in_sync,
+            self.skipped_bytes,
+            self.truncated_frames,
+        );
+        let Some((offset, header)) = self.next_frame()? else {
+            return Ok(None);
+        };
+        self.seek_to(offset)?;
+        let mut frame = vec![0u8; header.frame_len()];
+        let tag = match self.reader.read_exact(&mut frame) {
+            Ok(()) => InfoTag::parse(&header, &frame),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => return Err(err),
+        };
+        match tag {
+            Some(tag) => {
+                self.position = offset + frame.len() as u64;
+                Ok(Some((offset, header, tag)))
+            }
+            None => {
+                // The frames will be scanned again, so they're counted then. Reading the frame
+                // may have stopped anywhere at the end of the stream, so this seek isn't relative.
+                let (position, in_sync, skipped_bytes, truncated_frames) = start;
+                self.reader.seek(SeekFrom::Start(position))?;
+                self.position = position;
+                self.in_sync = in_sync;
+                self.skipped_bytes = skipped_bytes;
+                self.truncated_frames = truncated_frames;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Return the byte offset and header of the next frame, or None at the end of the stream.
+    ///
+    /// Bytes that don't start a valid frame header are skipped one at a time until the stream is
+    /// back in sync. Junk data can look like a frame header by chance, so after skipping data a
+    /// header is only trusted if it's followed by the header of another frame from the same stream.
+    pub fn next_frame(&mut self) -> io::Result<Option<(u64, FrameHeader)>> {
+        loop {
+            let offset = self.position;
+            if offset + HEADER_LEN as u64 > self.end {
+                self.skipped_bytes += self.end.saturating_sub(offset);
+                return Ok(None);
+            }
+            let mut header = [0u8; HEADER_LEN];
+            self.reader.read_exact(&mut header)?;
+            self.position += HEADER_LEN as u64;
+            let Some(header) = FrameHeader::parse(header) else {
+                self.resync(offset)?;
+                continue;
+            };
+            let frame_end = offset + header.frame_len() as u64;
+            if frame_end > self.end {
+                if !self.in_sync {
+                    self.resync(offset)?;
+                    continue;
+                }
+                // The stream was cut off in the middle of this frame
+                self.truncated_frames += 1;
+                self.skipped_bytes += self.end - offset;
+                self.seek_to(self.end)?;
+                return Ok(None);
+            }
+            self.reader
+                .seek_relative((header.frame_len() - HEADER_LEN) as i64)?;
+            self.position = frame_end;
+
+            if !self.in_sync && frame_end + HEADER_LEN as u64 <= self.end {
+                let mut next = [0u8; HEADER_LEN];
+                self.reader.read_exact(&mut next)?;
+                self.reader.seek_relative(-(HEADER_LEN as i64))?;
+                if !FrameHeader::parse(next).is_some_and(|next| next.follows(&header)) {
+                    self.resync(offset)?;
+                    continue;
+                }
+            }
+            self.in_sync = true;
+            return Ok(Some((offset, header)));
+        }
+    }
+
+    /// Skip the byte at `offset`, which doesn't start a valid frame, to look for a frame header
+    /// at the next byte.
+    fn resync(&mut self, offset: u64) -> io::Result<()> {
+        self.seek_to(offset + 1)?;
+        self.skipped_bytes += 1;
+        self.in_sync = false;
+        Ok(())
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        self.reader.seek_relative(len as i64)?;
+        self.position += len;
+        Ok(())
+    }
+
+    /// Move to `position`. The seek is relative, which keeps the buffered data when `position`
+    /// is within it, so skipping junk a byte at a time doesn't read the stream again.
+    fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        self.reader
+            .seek_relative(position as i64 - self.position as i64)?;
+        self.position = position;
+        Ok(())
+    }
+}
+
+/// The byte offset of the end of the audio data in a stream, before any ID3v1 tag at the end.
+pub fn audio_end(mut reader: impl Read + Seek) -> io::Result<u64> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    if end >= ID3V1_LEN {
+        reader.seek(SeekFrom::Start(end - ID3V1_LEN))?;
+        let mut tag = [0u8; 3];
+        reader.read_exact(&mut tag)?;
+        if &tag == b"TAG" {
+            return Ok(end - ID3V1_LEN);
+        }
+    }
+    Ok(end)
+}
+
+/// Decodes the frames of an MPEG audio stream.
+pub struct FrameDecoder<R> {
+    decoder: nanomp3::Decoder,
+    reader: R,
+    /// Bytes read from the stream that haven't been consumed by the decoder yet
+    buffer: Vec<u8>,
+    eof: bool,
+    /// Whether the last frame was directly followed by the start of the buffer
+    in_sync: bool,
+    /// Whether a frame has been decoded yet
+    synced: bool,
+    skipped_bytes: u64,
+}
+
+impl<R: Read> FrameDecoder<R> {
+    /// The number of bytes kept buffered ahead of the decoder. This needs to hold at least a
+    /// couple of frames, and the largest MPEG audio frames are under 3 KiB.
+    const BUFFER_LEN: usize = 16 * 1024;
+
+    pub fn new(reader: R) -> Self {
+        Self {
+            decoder: nanomp3::Decoder::new(),
+            reader,
+            buffer: Vec::with_capacity(Self::BUFFER_LEN),
+            eof: false,
+            in_sync: false,
+            synced: false,
+            skipped_bytes: 0,
+        }
+    }
+
+    /// Decode the next frame into `samples`, returning None at the end of the stream.
+    ///
+    /// Frames are found the same way as by [`FrameScanner::next_frame`], and given to the decoder
+    /// one at a time. Left to find frames itself, the decoder drops the frames before junk data,
+    /// as it only syncs to a frame followed by several others. Data that doesn't start a frame is
+    /// skipped up to the next byte that could, so corrupt data can't stall decoding.
+    ///
+    /// A frame whose audio can't be decoded is returned as silence, so the frames after it keep
+    /// their place in time. The first frame decoded after a seek usually continues from the
+    /// frames before it, so it's one of these. Undecodable frames found after skipping data are
+    /// dropped instead, as they're likely junk.
+    pub fn next_frame(
+        &mut self,
+        samples: &mut [f32; nanomp3::MAX_SAMPLES_PER_FRAME],
+    ) -> io::Result<Option<nanomp3::FrameInfo>> {
+        loop {
+            self.fill_buffer()?;
+            if self.buffer.is_empty() {
+                return Ok(None);
+            }
+            let Some(header) = self.frame_header() else {
+                self.in_sync = false;
+                let skip = self.next_header();
+                self.buffer.drain(..skip);
+                if self.synced {
+                    self.skipped_bytes += skip as u64;
+                }
+                continue;
+            };
+            // A frame found after skipping data could be junk that looks like a frame by chance
+            let trusted = self.in_sync || !self.synced;
+            let frame_len = header.frame_len();
+            let (_, frame_info) = self.decoder.decode(&self.buffer[..frame_len], samples);
+            self.buffer.drain(..frame_len);
+            self.in_sync = frame_info.is_some() || trusted;
+            if let Some(frame_info) = frame_info {
+                self.synced = true;
+                return Ok(Some(frame_info));
+            }
+            // The frame's audio data couldn't be decoded, like when it continues from frames
+            // before the decoder's starting point
+            if self.synced {
+                self.skipped_bytes += frame_len as u64;
+            }
+            if !trusted {
+                continue;
+            }
+            let len = header.samples() as usize * header.channels as usize;
+            samples[..len].fill(0.0);
+            return Ok(Some(nanomp3::FrameInfo {
+                samples_produced: header.samples() as usize,
+                channels: match header.channels {
+                    1 => nanomp3::Channels::Mono,
+                    _ => nanomp3::Channels::Stereo,
+                },
+                sample_rate: header.sample_rate,
+                bitrate: header.bitrate / 1000,
+            }));
+        }
+    }
+
+    /// The header of the frame at the start of the buffer, or None if it doesn't start with a
+    /// whole frame. After skipping data, a header is only trusted if it's followed by the header
+    /// of another frame from the same stream.
+    fn frame_header(&self) -> Option<FrameHeader> {
+        let header = FrameHeader::parse(self.buffer.get(..HEADER_LEN)?.try_into().unwrap())?;
+        let frame_len = header.frame_len();
+        if frame_len > self.buffer.len() {
+            // Only at the end of the stream, the buffer holds several frames otherwise
+            return None;
+        }
+        if !self.in_sync
+            && let Some(next) = self.buffer.get(frame_len..frame_len + HEADER_LEN)
+            && !FrameHeader::parse(next.try_into().unwrap())
+                .is_some_and(|next| next.follows(&header))
+        {
+            return None;
+        }
+        Some(header)
+    }
+
+    /// The position in the buffer of the next byte after the first that could start a frame
+    /// header, or the end of the buffer without one.
+    fn next_header(&self) -> usize {
+        self.buffer
+            .windows(HEADER_LEN)
+            .skip(1)
+            .position(|bytes| FrameHeader::parse(bytes.try_into().unwrap()).is_some())
+            .map_or(
+                self.buffer.len().saturating_sub(HEADER_LEN - 1).max(1),
+                |position| position + 1,
+            )
+    }
+
+    /// Read from the stream until the buffer is full or the stream has been read to the end.
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        while !self.eof && self.buffer.len() < Self::BUFFER_LEN {
+            let len = self.buffer.len();
+            self.buffer.resize(Self::BUFFER_LEN, 0);
+            let read = self.reader.read(&mut self.buffer[len..])?;
+            self.buffer.truncate(len + read);
+            self.eof = read == 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> AudioDecoder for FrameDecoder<R> {
+    fn decode(&mut self, samples: &mut Vec<f32>) -> anyhow::Result<Option<FrameFormat>> {
+        let mut pcm = [0f32; nanomp3::MAX_SAMPLES_PER_FRAME];
+        let Some(frame_info) = self.next_frame(&mut pcm)? else {
+            return Ok(None);
+        };
+        let channels = frame_info.channels.num() as usize;
+        samples.clear();
+        samples.extend_from_slice(&pcm[..frame_info.samples_produced * channels]);
+        Ok(Some(FrameFormat {
+            channels,
+            sample_rate: frame_info.sample_rate,
+        }))
+    }
+
+    /// Data skipped before the first frame isn't counted, as decoding can start in the middle of
+    /// a frame.
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+}
+
+/// The total length of an ID3v2 tag at the start of `buf`, or 0 if there is no tag.
+pub fn id3v2_len(buf: &[u8]) -> u64 {
+    if buf.len() < 10 || &buf[..3] != b"ID3" {
+        return 0;
+    }
+    // The tag size is stored as a 28 bit "synchsafe" integer, 7 bits per byte.
+    let size = buf[6..10]
+        .iter()
+        .fold(0u64, |size, &b| (size << 7) | (b & 0x7f) as u64);
+    let has_footer = buf[5] & 0x10 != 0;
+    10 + size + if has_footer { 10 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, mono, no CRC
+    const HEADER: [u8; HEADER_LEN] = [0xff, 0xfb, 0x90, 0xc0];
+
+    /// A frame of silence: all of the side information and audio data is zero.
+    fn silent_frame() -> Vec<u8> {
+        let header = FrameHeader::parse(HEADER).unwrap();
+        let mut frame = vec![0u8; header.frame_len()];
+        frame[..HEADER_LEN].copy_from_slice(&HEADER);
+        frame
+    }
+
+    /// A frame holding a Xing tag with a frame count, byte count and table of contents.
+    fn xing_frame(frames: u32, bytes: u32) -> Vec<u8> {
+        let mut frame = silent_frame();
+        let tag = HEADER_LEN + 17;
+        frame[tag..tag + 4].copy_from_slice(b"Xing");
+        frame[tag + 4..tag + 8].copy_from_slice(&0x7u32.to_be_bytes());
+        frame[tag + 8..tag + 12].copy_from_slice(&frames.to_be_bytes());
+        frame[tag + 12..tag + 16].copy_from_slice(&bytes.to_be_bytes());
+        for i in 0..100 {
+            frame[tag + 16 + i] = (i * 256 / 100) as u8;
+        }
+        frame
+    }
+
+    fn scan(data: &[u8]) -> Vec<(u64, FrameHeader)> {
+        let mut scanner = FrameScanner::new(Cursor::new(data)).unwrap();
+        std::iter::from_fn(|| scanner.next_frame().unwrap()).collect()
+    }
+
+    fn decode(data: &[u8]) -> (Vec<nanomp3::FrameInfo>, u64) {
+        let mut decoder = FrameDecoder::new(Cursor::new(data));
+        let mut pcm = [0f32; nanomp3::MAX_SAMPLES_PER_FRAME];
+        let frames = std::iter::from_fn(|| decoder.next_frame(&mut pcm).unwrap()).collect();
+        (frames, decoder.skipped_bytes())
+    }
+
+    /// Pseudo-random garbage, with some bytes that look like the start of a frame header so the
+    /// scanner and decoder find false syncs.
+    fn garbage(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let mut data: Vec<u8> = (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                match state % 16 {
+                    0 => 0xff,
+                    1 => HEADER[1],
+                    _ => (state >> 32) as u8,
+                }
+            })
+            .collect();
+        // Don't start with an ID3 tag
+        if let Some(first) = data.first_mut() {
+            *first = 0;
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = FrameHeader::parse(HEADER).unwrap();
+        assert_eq!(header.version, Version::Mpeg1);
+        assert_eq!(header.layer, Layer::Layer3);
+        assert_eq!(header.bitrate, 128_000);
+        assert_eq!(header.sample_rate, 44_100);
+        assert_eq!(header.channels, 1);
+        assert_eq!(header.frame_len(), 417);
+        assert_eq!(header.samples(), 1152);
+
+        // MPEG-2 Layer III, 64 kbit/s, 22.05 kHz, mono, padded
+        let header = FrameHeader::parse([0xff, 0xf3, 0x82, 0xc4]).unwrap();
+        assert_eq!(header.version, Version::Mpeg2);
+        assert_eq!(header.sample_rate, 22_050);
+        assert_eq!(header.frame_len(), 209);
+        assert_eq!(header.samples(), 576);
+
+        assert_eq!(FrameHeader::parse([0xff, 0xfb, 0xf0, 0xc0]), None);
+        assert_eq!(FrameHeader::parse([0x00, 0xfb, 0x90, 0xc0]), None);
+    }
+
+    #[test]
+    fn test_scan_skips_id3_and_junk() {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x05hello".to_vec();
+        data.extend(silent_frame());
+        data.extend(b"junk");
+        data.extend(silent_frame());
+
+        let offsets: Vec<u64> = scan(&data).iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, vec![15, 15 + 417 + 4]);
+    }
+
+    /// A reader that counts how many times it's read from.
+    struct CountingReader<R> {
+        reader: R,
+        reads: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.set(self.reads.get() + 1);
+            self.reader.read(buf)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.reader.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_scan_junk_without_rereading() {
+        let mut data = silent_frame();
+        data.extend([0x55; 64 * 1024]);
+        data.extend(silent_frame());
+        let reads = std::rc::Rc::default();
+        let reader = CountingReader {
+            reader: Cursor::new(&data),
+            reads: std::rc::Rc::clone(&reads),
+        };
+        let mut scanner = FrameScanner::new(reader).unwrap();
+        assert_eq!(
+            std::iter::from_fn(|| scanner.next_frame().unwrap()).count(),
+            2
+        );
+        // The junk is skipped a byte at a time, but only read once
+        assert!(reads.get() < 20, "{} reads", reads.get());
+    }
+
+    #[test]
+    fn test_scan_rejects_false_sync() {
+        // A header that isn't followed by another frame after it
+        let mut data = silent_frame();
+        data.extend(b"xx");
+        data.extend(HEADER);
+        data.extend([0; 100]);
+        data.extend(silent_frame());
+        data.extend(silent_frame());
+
+        let mut scanner = FrameScanner::new(Cursor::new(&data)).unwrap();
+        let offsets: Vec<u64> = std::iter::from_fn(|| scanner.next_frame().unwrap())
+            .map(|(offset, _)| offset)
+            .collect();
+        assert_eq!(offsets, vec![0, 523, 940]);
+        assert_eq!(scanner.skipped_bytes(), 106);
+        assert_eq!(scanner.truncated_frames(), 0);
+    }
+
+    #[test]
+    fn test_scan_truncated() {
+        let data: Vec<u8> = (0..5).flat_map(|_| silent_frame()).collect();
+        for len in 0..=data.len() {
+            let mut scanner = FrameScanner::new(Cursor::new(&data[..len])).unwrap();
+            let frames = std::iter::from_fn(|| scanner.next_frame().unwrap()).count();
+            assert_eq!(frames, len / 417, "{len}");
+            let partial = len % 417;
+            assert_eq!(scanner.skipped_bytes(), partial as u64, "{len}");
+            assert_eq!(
+                scanner.truncated_frames(),
+                u64::from(partial >= HEADER_LEN),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scan_garbage() {
+        for seed in 0..200 {
+            let mut data = garbage(seed, (seed as usize * 37) % 4096);
+            // Some of the inputs have real frames in among the garbage
+            if seed % 2 == 0 {
+                let at = data.len() / 2;
+                data.splice(at..at, (0..3).flat_map(|_| silent_frame()));
+            }
+            let mut scanner = FrameScanner::new(Cursor::new(&data)).unwrap();
+            let mut end = 0;
+            let mut frame_bytes = 0;
+            while let Some((offset, header)) = scanner.next_frame().unwrap() {
+                assert!(offset >= end, "{seed}: frames overlap");
+                end = offset + header.frame_len() as u64;
+                assert!(end <= data.len() as u64, "{seed}: frame past the end");
+                frame_bytes += header.frame_len() as u64;
+            }
+            // Every byte is either part of a frame or skipped
+            assert_eq!(
+                frame_bytes + scanner.skipped_bytes(),
+                scanner.end(),
+                "{seed}"
+            );
+            if seed % 2 == 0 {
+                assert!(frame_bytes >= 2 * 417, "{seed}: frames missed");
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_truncated_and_garbage() {
+        let data: Vec<u8> = (0..5).flat_map(|_| silent_frame()).collect();
+        let (frames, skipped) = decode(&data);
+        assert_eq!((frames.len(), skipped), (5, 0));
+        for len in (0..data.len()).step_by(7) {
+            let (frames, skipped) = decode(&data[..len]);
+            assert!(frames.len() <= len / 417, "{len}");
+            assert!(skipped <= len as u64, "{len}");
+        }
+
+        for seed in 0..200 {
+            let data = garbage(seed, (seed as usize * 53) % 8192);
+            let (_, skipped) = decode(&data);
+            assert!(skipped <= data.len() as u64, "{seed}");
+        }
+        // Decoding picks up again after garbage between frames
+        let mut data: Vec<u8> = (0..3).flat_map(|_| silent_frame()).collect();
+        data.extend(garbage(1, 1000));
+        data.extend((0..3).flat_map(|_| silent_frame()));
+        let (frames, skipped) = decode(&data);
+        assert_eq!(frames.len(), 6);
+        assert!(skipped > 0);
+        // None of the frames around the garbage are lost
+        for seed in 0..100 {
+            let mut data: Vec<u8> = (0..3).flat_map(|_| silent_frame()).collect();
+            data.extend(garbage(seed, (seed as usize * 97) % 3000));
+            data.extend((0..3).flat_map(|_| silent_frame()));
+            assert_eq!(decode(&data).0.len(), 6, "{seed}");
+        }
+    }
+
+    #[test]
+    fn test_decode_frame_continuing_from_earlier_frames() {
+        // The frames after the first take some of their audio data from the frame before,
+        // like when a decoder starts after a seek
+        let mut data: Vec<u8> = (0..4).flat_map(|_| silent_frame()).collect();
+        for frame in data.chunks_mut(417) {
+            frame[HEADER_LEN] = 5; // main_data_begin is 10 bytes back
+        }
+        let (frames, skipped) = decode(&data[417..]);
+        assert_eq!(skipped, 0);
+        // The first frame can't be decoded without the one before, it's returned as silence
+        let samples: Vec<usize> = frames.iter().map(|frame| frame.samples_produced).collect();
+        assert_eq!(samples, vec![1152; 3]);
+    }
+
+    #[test]
+    fn test_scanned_frames_match_decoder() {
+        let data: Vec<u8> = (0..20).flat_map(|_| silent_frame()).collect();
+        let frames = scan(&data);
+
+        let mut decoder = nanomp3::Decoder::new();
+        let mut pcm = [0f32; nanomp3::MAX_SAMPLES_PER_FRAME];
+        let mut pos = 0;
+        let mut decoded = vec![];
+        while pos < data.len() {
+            let (consumed, frame_info) = decoder.decode(&data[pos..], &mut pcm);
+            if let Some(frame_info) = frame_info {
+                decoded.push(frame_info);
+            }
+            if consumed == 0 {
+                break;
+            }
+            pos += consumed;
+        }
+
+        assert_eq!(frames.len(), 20);
+        assert_eq!(frames.len(), decoded.len());
+        for ((_, header), frame_info) in frames.iter().zip(&decoded) {
+            assert_eq!(header.samples() as usize, frame_info.samples_produced);
+            assert_eq!(header.sample_rate, frame_info.sample_rate);
+            assert_eq!(header.channels, frame_info.channels.num());
+        }
+    }
+
+    #[test]
+    fn test_read_xing_tag() {
+        let mut data = xing_frame(3, 4 * 417);
+        data.extend((0..3).flat_map(|_| silent_frame()));
+
+        let mut scanner = FrameScanner::new(Cursor::new(&data)).unwrap();
+        let (offset, _, tag) = scanner.read_info_tag().unwrap().unwrap();
+        assert_eq!(offset, 0);
+        assert_eq!(tag.frames, Some(3));
+        assert_eq!(tag.bytes, Some(4 * 417));
+        assert!(!tag.constant_bitrate);
+
+        // The scanner continues from the first audio frame
+        assert_eq!(scanner.next_frame().unwrap().unwrap().0, 417);
+    }
+
+    #[test]
+    fn test_read_vbri_tag() {
+        let mut frame = silent_frame();
+        let mut tag = b"VBRI".to_vec();
+        tag.extend([0, 1, 0, 0, 0, 0]); // version, delay, quality
+        tag.extend(1000u32.to_be_bytes()); // bytes
+        tag.extend(4u32.to_be_bytes()); // frames
+        tag.extend([0, 2, 0, 1, 0, 2, 0, 2]); // entries, scale, entry length, frames per entry
+        tag.extend([1, 0x90, 0, 0]); // 400 bytes, then the rest
+        frame[36..36 + tag.len()].copy_from_slice(&tag);
+
+        let header = FrameHeader::parse(HEADER).unwrap();
+        let tag = InfoTag::parse(&header, &frame).unwrap();
+        assert_eq!(tag.frames, Some(4));
+        assert_eq!(tag.bytes, Some(1000));
+        assert!(!tag.constant_bitrate);
+    }
+
+    #[test]
+    fn test_no_info_tag() {
+        let data: Vec<u8> = (0..2).flat_map(|_| silent_frame()).collect();
+        let mut scanner = FrameScanner::new(Cursor::new(&data)).unwrap();
+        assert!(scanner.read_info_tag().unwrap().is_none());
+        assert_eq!(scan(&data).len(), 2);
+        assert_eq!(scanner.next_frame().unwrap().unwrap().0, 0);
+    }
+}
@@ -0,0 +1,629 @@
+//! Parsing of ID3v2 and ID3v1 metadata tags.
+//!
+//! Only the frames published by the loader are read: title, artist, album, comment, recording
+//! time, user defined text (`TXXX`) and attached pictures. ID3v2.2, 2.3 and 2.4 are supported.
+//!
+//! FLAC files are tagged with Vorbis comments instead, which are read into the same fields.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+};
+
+/// The length of the ID3v1 tag at the end of a file.
+pub const ID3V1_LEN: usize = 128;
+
+/// Metadata read from the ID3 tags of a file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Id3Tag {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub comment: Option<String>,
+    /// The recording time as an ISO 8601 string, e.g. `2025-03-01T12:00:00`. This may be only a
+    /// year for older tags.
+    pub recording_time: Option<String>,
+    /// User defined text frames (`TXXX`), keyed by description
+    pub user_text: BTreeMap<String, String>,
+    pub picture: Option<Picture>,
+}
+
+/// An image attached to the tag (`APIC`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Picture {
+    pub mime_type: String,
+    /// The ID3 picture type, 3 is the front cover
+    pub picture_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// The image format as named by `foxglove.CompressedImage`, if it is one Foxglove can display.
+    pub fn format(&self) -> Option<&'static str> {
+        match self.mime_type.to_ascii_lowercase().as_str() {
+            "image/jpeg" | "image/jpg" | "jpg" => Some("jpeg"),
+            "image/png" | "png" => Some("png"),
+            "image/webp" => Some("webp"),
+            "image/avif" => Some("avif"),
+            _ => None,
+        }
+    }
+}
+
+impl Id3Tag {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fill in fields missing from this tag with the values from `other`.
+    pub fn merge(&mut self, other: Id3Tag) {
+        self.title = self.title.take().or(other.title);
+        self.artist = self.artist.take().or(other.artist);
+        self.album = self.album.take().or(other.album);
+        self.comment = self.comment.take().or(other.comment);
+        self.recording_time = self.recording_time.take().or(other.recording_time);
+        for (key, value) in other.user_text {
+            self.user_text.entry(key).or_insert(value);
+        }
+        self.picture = self.picture.take().or(other.picture);
+    }
+
+    /// Read an ID3v2 tag from the current position of `reader`, which should be the start of the
+    /// file. Returns `None` without consuming more than the header if there is no tag, and an
+    /// error if the tag runs past the end of the file.
+    pub fn read_id3v2(mut reader: impl Read) -> io::Result<Option<Self>> {
+        let mut header = [0u8; 10];
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        if &header[..3] != b"ID3" {
+            return Ok(None);
+        }
+        // The size is read from the file, so the body is only allocated as it's read
+        let size = syncsafe(&header[6..10]) as u64;
+        let mut body = Vec::new();
+        reader.take(size).read_to_end(&mut body)?;
+        if (body.len() as u64) < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "ID3v2 tag runs past the end of the file",
+            ));
+        }
+        Ok(Self::parse_id3v2(header, &body))
+    }
+
+    fn parse_id3v2(header: [u8; 10], body: &[u8]) -> Option<Self> {
+        let version = header[3];
+        let flags = header[5];
+        if !(2..=4).contains(&version) {
+            return None;
+        }
+        // Before 2.4 unsynchronisation is applied to the whole tag rather than to each frame
+        let body = if flags & 0x80 != 0 && version < 4 {
+            remove_unsync(body)
+        } else {
+            body.to_vec()
+        };
+        let mut pos = 0;
+        if flags & 0x40 != 0 {
+            match version {
+                // In 2.2 this flag means the tag is compressed, with no defined compression scheme
+                2 => return None,
+                3 => pos = 4 + read_u32(&body, 0)? as usize,
+                _ => pos = syncsafe(body.get(..4)?) as usize,
+            }
+        }
+
+        let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+        let mut tag = Id3Tag::default();
+        let mut comments = vec![];
+        let mut date = DateFrames::default();
+        while pos + header_len <= body.len() {
+            let frame_header = &body[pos..pos + header_len];
+            // The rest of the tag is padding
+            if frame_header[0] == 0 {
+                break;
+            }
+            let size = match version {
+                2 => u32::from_be_bytes([0, frame_header[3], frame_header[4], frame_header[5]]),
+                3 => read_u32(frame_header, 4)?,
+                _ => syncsafe(&frame_header[4..8]),
+            } as usize;
+            let start = pos + header_len;
+            let Some(data) = body.get(start..start + size) else {
+                break;
+            };
+            pos = start + size;
+
+            let id = normalize_frame_id(&frame_header[..id_len]);
+            let data = match version {
+                3 => {
+                    let format_flags = frame_header[9];
+                    // Skip compressed and encrypted frames, and the group id of grouped frames
+                    if format_flags & 0xc0 != 0 {
+                        continue;
+                    }
+                    let Some(data) = data.get((format_flags & 0x20 != 0) as usize..) else {
+                        continue;
+                    };
+                    data.to_vec()
+                }
+                4 => {
+                    let format_flags = frame_header[9];
+                    if format_flags & 0x0c != 0 {
+                        continue;
+                    }
+                    let Some(mut data) = data.get((format_flags & 0x40 != 0) as usize..) else {
+                        continue;
+                    };
+                    // The data length indicator only matters for compressed frames
+                    if format_flags & 0x01 != 0 {
+                        data = data.get(4..).unwrap_or_default();
+                    }
+                    if format_flags & 0x02 != 0 {
+                        remove_unsync(data)
+                    } else {
+                        data.to_vec()
+                    }
+                }
+                _ => data.to_vec(),
+            };
+            let Some((&encoding, rest)) = data.split_first() else {
+                continue;
+            };
+            match id {
+                "TIT2" => tag.title = text_value(encoding, rest),
+                "TPE1" => tag.artist = text_value(encoding, rest),
+                "TALB" => tag.album = text_value(encoding, rest),
+                "TDRC" => date.recording_time = text_value(encoding, rest),
+                "TYER" => date.year = text_value(encoding, rest),
+                "TDAT" => date.day_month = text_value(encoding, rest),
+                "TIME" => date.time = text_value(encoding, rest),
+                "TXXX" => {
+                    let (description, value) = split_terminated(encoding, rest);
+                    if let Some(value) = text_value(encoding, value) {
+                        tag.user_text
+                            .insert(decode_text(encoding, description), value);
+                    }
+                }
+                "COMM" => {
+                    // The language code is followed by a short description and the comment text
+                    let Some(rest) = rest.get(3..) else { continue };
+                    let (description, text) = split_terminated(encoding, rest);
+                    if let Some(text) = text_value(encoding, text) {
+                        comments.push((description.is_empty(), text));
+                    }
+                }
+                "APIC" => {
+                    let picture = if version == 2 {
+                        // ID3v2.2 has a three character image format instead of a MIME type
+                        let Some(format) = rest.get(..3) else {
+                            continue;
+                        };
+                        let Some((&picture_type, rest)) = rest[3..].split_first() else {
+                            continue;
+                        };
+                        let (_description, data) = split_terminated(encoding, rest);
+                        Picture {
+                            mime_type: decode_text(0, format),
+                            picture_type,
+                            data: data.to_vec(),
+                        }
+                    } else {
+                        let (mime_type, rest) = split_terminated(0, rest);
+                        let Some((&picture_type, rest)) = rest.split_first() else {
+                            continue;
+                        };
+                        let (_description, data) = split_terminated(encoding, rest);
+                        Picture {
+                            mime_type: decode_text(0, mime_type),
+                            picture_type,
+                            data: data.to_vec(),
+                        }
+                    };
+                    // Prefer the front cover over any other pictures
+                    let is_better = tag.picture.as_ref().is_none_or(|current| {
+                        current.picture_type != FRONT_COVER && picture.picture_type == FRONT_COVER
+                    });
+                    if is_better {
+                        tag.picture = Some(picture);
+                    }
+                }
+                _ => (),
+            }
+        }
+        // Prefer a comment without a description, which is the "main" comment of the file
+        comments.sort_by_key(|(no_description, _)| !no_description);
+        tag.comment = comments.into_iter().next().map(|(_, text)| text);
+        tag.recording_time = date.into_iso8601();
+        Some(tag)
+    }
+
+    /// Parse an ID3v1 tag from the last [`ID3V1_LEN`] bytes of a file.
+    pub fn parse_id3v1(buf: &[u8]) -> Option<Self> {
+        if buf.len() != ID3V1_LEN || &buf[..3] != b"TAG" {
+            return None;
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let text = decode_text(0, &buf[range]);
+            let text = text.trim_end_matches([' ', '\0']);
+            (!text.is_empty()).then(|| text.to_string())
+        };
+        // In ID3v1.1 the last two bytes of the comment hold a track number
+        let comment_end = if buf[125] == 0 && buf[126] != 0 {
+            125
+        } else {
+            127
+        };
+        Some(Self {
+            title: field(3..33),
+            artist: field(33..63),
+            album: field(63..93),
+            recording_time: field(93..97),
+            comment: field(97..comment_end),
+            ..Default::default()
+        })
+    }
+
+    /// Parse a Vorbis comment block. The title, artist, album, comment and date fields fill in the
+    /// matching ID3 fields, and every other field is kept as user defined text. Field names are
+    /// case insensitive, so they're stored in upper case.
+    pub fn parse_vorbis_comment(data: &[u8]) -> Option<Self> {
+        let mut rest = data;
+        let _vendor = take_vorbis_string(&mut rest)?;
+        let count = take_u32_le(&mut rest)?;
+        let mut tag = Self::default();
+        for _ in 0..count {
+            let comment = String::from_utf8_lossy(take_vorbis_string(&mut rest)?);
+            let Some((name, value)) = comment.split_once('=') else {
+                continue;
+            };
+            let name = name.to_ascii_uppercase();
+            let value = value.to_string();
+            let field = match name.as_str() {
+                "TITLE" => &mut tag.title,
+                "ARTIST" => &mut tag.artist,
+                "ALBUM" => &mut tag.album,
+                "COMMENT" | "DESCRIPTION" => &mut tag.comment,
+                "DATE" => &mut tag.recording_time,
+                _ => {
+                    tag.user_text.entry(name).or_insert(value);
+                    continue;
+                }
+            };
+            field.get_or_insert(value);
+        }
+        Some(tag)
+    }
+}
+
+const FRONT_COVER: u8 = 3;
+
+/// The ID3v2.3 date frames, which split the recording time across several frames.
+#[derive(Default)]
+struct DateFrames {
+    /// The ID3v2.4 `TDRC` frame, which holds the whole timestamp
+    recording_time: Option<String>,
+    /// `YYYY`
+    year: Option<String>,
+    /// `DDMM`
+    day_month: Option<String>,
+    /// `HHMM`
+    time: Option<String>,
+}
+
+impl DateFrames {
+    fn into_iso8601(self) -> Option<String> {
+        if self.recording_time.is_some() {
+            return self.recording_time;
+        }
+        let mut date = self.year?;
+        let digits = |s: &String| s.len() == 4 && s.bytes().all(|b| b.is_ascii_digit());
+        if let Some(day_month) = self.day_month.filter(digits) {
+            date += &format!("-{}-{}", &day_month[2..], &day_month[..2]);
+            if let Some(time) = self.time.filter(digits) {
+                date += &format!("T{}:{}", &time[..2], &time[2..]);
+            }
+        }
+        Some(date)
+    }
+}
+
+/// Map ID3v2.2 frame ids to their ID3v2.3 equivalent.
+fn normalize_frame_id(id: &[u8]) -> &'static str {
+    match id {
+        b"TIT2" | b"TT2" => "TIT2",
+        b"TPE1" | b"TP1" => "TPE1",
+        b"TALB" | b"TAL" => "TALB",
+        b"TDRC" => "TDRC",
+        b"TYER" | b"TYE" => "TYER",
+        b"TDAT" | b"TDA" => "TDAT",
+        b"TIME" | b"TIM" => "TIME",
+        b"TXXX" | b"TXX" => "TXXX",
+        b"COMM" | b"COM" => "COMM",
+        b"APIC" | b"PIC" => "APIC",
+        _ => "",
+    }
+}
+
+/// Decode the value of a text frame. ID3v2.4 allows several null separated values, only the
+/// first is kept.
+fn text_value(encoding: u8, data: &[u8]) -> Option<String> {
+    let (value, _) = split_terminated(encoding, data);
+    let value = decode_text(encoding, value);
+    (!value.is_empty()).then_some(value)
+}
+
+/// Split `data` at the first null terminator for the text encoding, dropping the terminator.
+fn split_terminated(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+    let end = match encoding {
+        // UTF-16 strings are terminated by a null code unit
+        1 | 2 => data
+            .chunks_exact(2)
+            .position(|c| c == [0, 0])
+            .map(|i| (i * 2, 2)),
+        _ => data.iter().position(|&b| b == 0).map(|i| (i, 1)),
+    };
+    match end {
+        Some((end, len)) => (&data[..end], &data[end + len..]),
+        None => (data, &[]),
+    }
+}
+
+fn decode_text(encoding: u8, data: &[u8]) -> String {
+    let text = match encoding {
+        // ISO-8859-1 maps directly onto the first 256 code points
+        0 => data.iter().map(|&b| b as char).collect(),
+        1 | 2 => {
+            let (little_endian, data) = match data {
+                [0xff, 0xfe, rest @ ..] => (true, rest),
+                [0xfe, 0xff, rest @ ..] => (false, rest),
+                _ => (false, data),
+            };
+            let units = data.chunks_exact(2).map(|c| {
+                if little_endian {
+                    u16::from_le_bytes([c[0], c[1]])
+                } else {
+                    u16::from_be_bytes([c[0], c[1]])
+                }
+            });
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+        _ => String::from_utf8_lossy(data).into_owned(),
+    };
+    text.trim_end_matches('\0').to_string()
+}
+
+/// Undo unsynchronisation, which inserts a zero byte after every `0xff` so the tag can't contain
+/// an MPEG frame sync.
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0;
+    for &b in data {
+        if !(prev == 0xff && b == 0) {
+            out.push(b);
+        }
+        prev = b;
+    }
+    out
+}
+
+/// Read a 28 bit "synchsafe" integer, 7 bits per byte.
+/// Read a little endian `u32` from the start of `rest`, moving past it.
+fn take_u32_le(rest: &mut &[u8]) -> Option<u32> {
+    let (bytes, tail) = rest.split_first_chunk::<4>()?;
+    *rest = tail;
+    Some(u32::from_le_bytes(*bytes))
+}
+
+/// Read a Vorbis comment string, prefixed by its length, from the start of `rest`.
+fn take_vorbis_string<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = take_u32_le(rest)? as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (value, tail) = rest.split_at(len);
+    *rest = tail;
+    Some(value)
+}
+
+fn syncsafe(buf: &[u8]) -> u32 {
+    buf.iter()
+        .fold(0, |size, &b| (size << 7) | (b & 0x7f) as u32)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        buf.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(version: u8, id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        match version {
+            2 => frame.extend(&(data.len() as u32).to_be_bytes()[1..]),
+            3 => frame.extend((data.len() as u32).to_be_bytes()),
+            _ => frame.extend((0..4).rev().map(|i| (data.len() >> (7 * i)) as u8 & 0x7f)),
+        }
+        if version > 2 {
+            frame.extend([0, 0]);
+        }
+        frame.extend(data);
+        frame
+    }
+
+    fn tag(version: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = frames.concat();
+        // Padding
+        body.extend([0; 16]);
+        let mut tag = vec![b'I', b'D', b'3', version, 0, 0];
+        tag.extend((0..4).rev().map(|i| (body.len() >> (7 * i)) as u8 & 0x7f));
+        tag.extend(body);
+        tag
+    }
+
+    fn read(tag: &[u8]) -> Id3Tag {
+        Id3Tag::read_id3v2(tag).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_id3v24() {
+        let cover = [0xff, 0xd8, 0xff, 0xe0, 0x00];
+        let mut apic = b"\x03image/jpeg\x00\x03cover\x00".to_vec();
+        apic.extend(cover);
+        let tag = read(&tag(
+            4,
+            &[
+                frame(4, b"TIT2", b"\x03Engine run 3"),
+                frame(4, b"TPE1", b"\x03Test \xc3\xa9quipe\x00Second artist"),
+                frame(4, b"TDRC", b"\x032025-03-01T12:00:00"),
+                frame(4, b"COMM", b"\x03engdesc\x00not this one"),
+                frame(4, b"COMM", b"\x03eng\x00Microphone 2"),
+                frame(4, b"TXXX", b"\x03RECORDING_START\x001740830400.5"),
+                frame(4, b"APIC", &apic),
+                frame(4, b"XXXX", b"ignored"),
+            ],
+        ));
+        assert_eq!(tag.title.as_deref(), Some("Engine run 3"));
+        assert_eq!(tag.artist.as_deref(), Some("Test équipe"));
+        assert_eq!(tag.recording_time.as_deref(), Some("2025-03-01T12:00:00"));
+        assert_eq!(tag.comment.as_deref(), Some("Microphone 2"));
+        assert_eq!(
+            tag.user_text.get("RECORDING_START").map(String::as_str),
+            Some("1740830400.5")
+        );
+        let picture = tag.picture.unwrap();
+        assert_eq!(picture.format(), Some("jpeg"));
+        assert_eq!(picture.picture_type, FRONT_COVER);
+        assert_eq!(picture.data, cover);
+    }
+
+    #[test]
+    fn test_id3v23_utf16_and_date_frames() {
+        let mut title = vec![1, 0xff, 0xfe];
+        title.extend("Tïtle".encode_utf16().flat_map(u16::to_le_bytes));
+        let tag = read(&tag(
+            3,
+            &[
+                frame(3, b"TIT2", &title),
+                frame(3, b"TYER", b"\x002024"),
+                frame(3, b"TDAT", b"\x000103"),
+                frame(3, b"TIME", b"\x001530"),
+            ],
+        ));
+        assert_eq!(tag.title.as_deref(), Some("Tïtle"));
+        assert_eq!(tag.recording_time.as_deref(), Some("2024-03-01T15:30"));
+    }
+
+    #[test]
+    fn test_id3v22() {
+        let tag = read(&tag(
+            2,
+            &[
+                frame(2, b"TT2", b"\x00Old"),
+                frame(2, b"PIC", b"\x00PNG\x04\x00\x89PNG"),
+            ],
+        ));
+        assert_eq!(tag.title.as_deref(), Some("Old"));
+        let picture = tag.picture.unwrap();
+        assert_eq!(picture.format(), Some("png"));
+        assert_eq!(picture.data, b"\x89PNG");
+    }
+
+    #[test]
+    fn test_no_id3v2() {
+        assert_eq!(Id3Tag::read_id3v2(&[0xff, 0xfb, 0x90][..]).unwrap(), None);
+        assert_eq!(Id3Tag::read_id3v2(&[0; 32][..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_tag() {
+        let tag = tag(4, &[frame(4, b"TIT2", b"\x03Title")]);
+        let err = Id3Tag::read_id3v2(&tag[..tag.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        // A frame claiming to be larger than the tag stops parsing instead of reading past it
+        let mut tag = tag(3, &[frame(3, b"TIT2", b"\x00Title")]);
+        tag[17] = 0x7f;
+        assert_eq!(read(&tag), Id3Tag::default());
+    }
+
+    #[test]
+    fn test_empty_grouped_frame() {
+        // An empty frame with the grouping flag set has no room for its group id
+        for (version, grouping) in [(3, 0x20), (4, 0x40)] {
+            let mut empty = frame(version, b"TIT2", b"");
+            empty[9] = grouping;
+            let tag = read(&tag(
+                version,
+                &[empty, frame(version, b"TPE1", b"\x00Artist")],
+            ));
+            assert_eq!(tag.title, None);
+            assert_eq!(tag.artist.as_deref(), Some("Artist"));
+        }
+    }
+
+    #[test]
+    fn test_vorbis_comment() {
+        let comments = [
+            "reference libFLAC 1.4.3",
+            "TITLE=Engine run 3",
+            "artist=Test cell",
+            "Date=2025-03-01T12:00:00",
+            "recording_start=1740830400",
+            "not a field",
+        ];
+        let mut data = vec![];
+        for (i, comment) in comments.iter().enumerate() {
+            data.extend((comment.len() as u32).to_le_bytes());
+            data.extend(comment.as_bytes());
+            // The vendor string is followed by the number of comments
+            if i == 0 {
+                data.extend((comments.len() as u32 - 1).to_le_bytes());
+            }
+        }
+
+        let tag = Id3Tag::parse_vorbis_comment(&data).unwrap();
+        assert_eq!(tag.title.as_deref(), Some("Engine run 3"));
+        assert_eq!(tag.artist.as_deref(), Some("Test cell"));
+        assert_eq!(tag.recording_time.as_deref(), Some("2025-03-01T12:00:00"));
+        assert_eq!(
+            tag.user_text.get("RECORDING_START").map(String::as_str),
+            Some("1740830400")
+        );
+        assert_eq!(tag.user_text.len(), 1);
+
+        // Truncated in the middle of a comment
+        assert_eq!(Id3Tag::parse_vorbis_comment(&data[..data.len() - 3]), None);
+    }
+
+    #[test]
+    fn test_id3v1() {
+        let mut buf = [0u8; ID3V1_LEN];
+        buf[..3].copy_from_slice(b"TAG");
+        buf[3..8].copy_from_slice(b"Title");
+        buf[33..39].copy_from_slice(b"Artist");
+        buf[93..97].copy_from_slice(b"1999");
+        buf[97..104].copy_from_slice(b"Comment");
+        buf[126] = 7;
+        let tag = Id3Tag::parse_id3v1(&buf).unwrap();
+        assert_eq!(tag.title.as_deref(), Some("Title"));
+        assert_eq!(tag.artist.as_deref(), Some("Artist"));
+        assert_eq!(tag.album, None);
+        assert_eq!(tag.recording_time.as_deref(), Some("1999"));
+        assert_eq!(tag.comment.as_deref(), Some("Comment"));
+
+        assert_eq!(Id3Tag::parse_id3v1(&[0; ID3V1_LEN]), None);
+    }
+}
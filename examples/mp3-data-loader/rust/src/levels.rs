@@ -0,0 +1,246 @@
+//! Signal level measurements published alongside the audio.
+
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// The loudness reported for silence, in LUFS. This is the absolute gate of ITU-R BS.1770, below
+/// which audio isn't considered when measuring loudness.
+pub const MIN_LOUDNESS: f64 = -70.0;
+
+/// The RMS and peak level of a block of samples, as a fraction of full scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub rms: f64,
+    pub peak: f64,
+}
+
+impl Envelope {
+    /// Measure interleaved samples, across all channels.
+    pub fn measure(samples: &[f32]) -> Self {
+        if samples.is_empty() {
+            return Self {
+                rms: 0.0,
+                peak: 0.0,
+            };
+        }
+        let mut sum_squares = 0.0;
+        let mut peak: f64 = 0.0;
+        for &sample in samples {
+            let sample = sample as f64;
+            sum_squares += sample * sample;
+            peak = peak.max(sample.abs());
+        }
+        Self {
+            rms: (sum_squares / samples.len() as f64).sqrt(),
+            peak,
+        }
+    }
+}
+
+/// Measures short-term loudness as defined by ITU-R BS.1770 and EBU R 128: the K-weighted mean
+/// square of the last few seconds of audio, summed across channels.
+pub struct LoudnessMeter {
+    /// The measurement window in samples per channel
+    window: u64,
+    window_ns: u64,
+    sample_rate: u32,
+    /// The K-weighting filters for each channel
+    filters: Vec<KWeighting>,
+    /// The number of samples per channel and the summed K-weighted energy of each block in the
+    /// window, oldest first
+    blocks: VecDeque<(u64, f64)>,
+    samples: u64,
+    energy: f64,
+}
+
+impl LoudnessMeter {
+    /// Create a meter averaging over `window_ns` nanoseconds, 3 s for short-term loudness.
+    pub fn new(window_ns: u64) -> Self {
+        Self {
+            window: 0,
+            window_ns,
+            sample_rate: 0,
+            filters: Vec::new(),
+            blocks: VecDeque::new(),
+            samples: 0,
+            energy: 0.0,
+        }
+    }
+
+    /// Add a block of interleaved samples and return the loudness of the window ending with it,
+    /// in LUFS.
+    ///
+    /// The meter is reset if the sample rate or channel count changes.
+    pub fn push(&mut self, samples: &[f32], channels: usize, sample_rate: u32) -> f64 {
+        if sample_rate != self.sample_rate || channels != self.filters.len() {
+            self.sample_rate = sample_rate;
+            self.window = self.window_ns * sample_rate as u64 / 1_000_000_000;
+            self.filters = (0..channels)
+                .map(|_| KWeighting::new(sample_rate))
+                .collect();
+            self.blocks.clear();
+            self.samples = 0;
+            self.energy = 0.0;
+        }
+        let mut energy = 0.0;
+        for frame in samples.chunks_exact(channels) {
+            for (filter, &sample) in self.filters.iter_mut().zip(frame) {
+                let weighted = filter.process(sample as f64);
+                energy += weighted * weighted;
+            }
+        }
+        let len = (samples.len() / channels) as u64;
+        self.blocks.push_back((len, energy));
+        self.samples += len;
+        self.energy += energy;
+        // Drop whole blocks that have fallen out of the window
+        while let Some(&(len, energy)) = self.blocks.front() {
+            if self.samples - len < self.window {
+                break;
+            }
+            self.blocks.pop_front();
+            self.samples -= len;
+            self.energy -= energy;
+        }
+
+        if self.samples == 0 || self.energy <= 0.0 {
+            return MIN_LOUDNESS;
+        }
+        let loudness = -0.691 + 10.0 * (self.energy / self.samples as f64).log10();
+        loudness.max(MIN_LOUDNESS)
+    }
+}
+
+/// The K-weighting filter from ITU-R BS.1770: a high shelf modelling the acoustic effect of the
+/// head, followed by a high pass filter.
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    /// The standard gives coefficients for 48 kHz, these are the analog prototypes used to derive
+    /// coefficients for any sample rate.
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(sample))
+    }
+}
+
+/// A second order IIR filter with a normalized `a0`, in transposed direct form II.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NS_PER_S: u64 = 1_000_000_000;
+
+    fn sine(frequency: f64, amplitude: f64, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                (amplitude * (2.0 * PI * frequency * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_envelope() {
+        let envelope = Envelope::measure(&sine(1000.0, 0.5, 48_000, 4800));
+        assert!((envelope.peak - 0.5).abs() < 1e-3, "{envelope:?}");
+        assert!(
+            (envelope.rms - 0.5 / 2f64.sqrt()).abs() < 1e-3,
+            "{envelope:?}"
+        );
+
+        let envelope = Envelope::measure(&[0.25, -1.0, 0.0, 0.0]);
+        assert_eq!(envelope.peak, 1.0);
+        assert_eq!(envelope.rms, (1.0625f64 / 4.0).sqrt());
+
+        assert_eq!(Envelope::measure(&[]).peak, 0.0);
+    }
+
+    #[test]
+    fn test_loudness_of_sine() {
+        // BS.1770: a 0 dBFS 1 kHz sine on one channel measures -3.01 LKFS
+        for sample_rate in [44_100, 48_000] {
+            let mut meter = LoudnessMeter::new(3 * NS_PER_S);
+            let samples = sine(1000.0, 1.0, sample_rate, sample_rate as usize);
+            let mut loudness = 0.0;
+            for block in samples.chunks(1152) {
+                loudness = meter.push(block, 1, sample_rate);
+            }
+            assert!((loudness + 3.01).abs() < 0.05, "{sample_rate}: {loudness}");
+        }
+
+        // The same sine on both channels of a stereo signal is 3 dB louder
+        let mut meter = LoudnessMeter::new(3 * NS_PER_S);
+        let stereo: Vec<f32> = sine(1000.0, 0.1, 48_000, 48_000)
+            .into_iter()
+            .flat_map(|sample| [sample, sample])
+            .collect();
+        let loudness = meter.push(&stereo, 2, 48_000);
+        assert!((loudness + 20.0).abs() < 0.05, "{loudness}");
+    }
+
+    #[test]
+    fn test_loudness_window() {
+        let mut meter = LoudnessMeter::new(NS_PER_S);
+        assert_eq!(meter.push(&[0.0; 48_000], 1, 48_000), MIN_LOUDNESS);
+        let loud = sine(1000.0, 1.0, 48_000, 48_000);
+        assert!(meter.push(&loud, 1, 48_000) > -3.1);
+        // Once the loud block is out of the window only the filters ringing out are measured
+        assert!(meter.push(&[0.0; 48_000], 1, 48_000) < -40.0);
+    }
+}
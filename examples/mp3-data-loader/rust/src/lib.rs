@@ -1,26 +1,226 @@
-use std::{collections::BTreeMap, io::Read, sync::Arc};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{Cursor, Read, Seek, SeekFrom},
+};
 
 use foxglove::Encode;
 use foxglove_data_loader::{
-    DataLoader, DataLoaderArgs, Initialization, Message, MessageIterator, MessageIteratorArgs,
-    reader::{self},
+    BackfillArgs, DataLoader, DataLoaderArgs, Initialization, LinkedChannel, Message,
+    MessageIterator, MessageIteratorArgs, Problem,
 };
 
 use anyhow::Context;
+use data_loader_utils::{TimeIndex, console, reader};
+
+mod flac;
+mod frame;
+mod id3;
+mod levels;
+mod ogg;
+mod pcm;
+mod resample;
+mod spectrogram;
+mod timestamp;
+mod wav;
+
+use flac::{FlacDecoder, FlacInfo, StreamInfo};
+use frame::{ConstantBitrate, FrameDecoder, InfoTag};
+use id3::{ID3V1_LEN, Id3Tag};
+use levels::{Envelope, LoudnessMeter};
+use ogg::{OggDecoder, OggInfo, VorbisStream};
+use pcm::{PcmEncoder, PcmFormat};
+use resample::Resampler;
+use spectrogram::{Spectrogram, SpectrogramConfig};
+use wav::{WavDecoder, WavFormat};
 
 const NS_PER_S: u64 = 1_000_000_000;
 
+/// The ID3 user defined text field (`TXXX`) or Vorbis comment holding the recording start time,
+/// either as a timestamp or as seconds since the Unix epoch. This takes priority over the
+/// recording time in the tags and any timestamp in the filename.
+const START_TIME_FIELD: &str = "RECORDING_START";
+
+/// The start time in nanoseconds since the Unix epoch used when none is found in the tags or the
+/// filename.
+const FALLBACK_START_TIME: u64 = 0;
+
+/// The sample format of the published audio. Use `PcmFormat::F32` to publish the decoder output
+/// without quantizing it to 16 bits.
+const OUTPUT_FORMAT: PcmFormat = PcmFormat::S16;
+
+/// Whether to add dither noise when quantizing to 16 bits, which trades a slightly higher noise
+/// floor for quantization error that doesn't correlate with the signal.
+const DITHER: bool = false;
+
+/// The number of frames decoded and discarded before the requested start time when seeking.
+///
+/// Layer III frames can store part of their audio data in earlier frames (the bit reservoir), and
+/// the decoder overlaps each frame with the previous one, so the first frames decoded after a seek
+/// are incomplete. Decoding a few frames early gives clean audio from the first emitted frame.
+const WARMUP_FRAMES: u64 = 4;
+
+/// The sample rate in Hz to convert all audio to, or `None` to publish audio at the rate it was
+/// recorded. Files can mix sample rates between frames, converting them gives every message the
+/// same rate.
+const OUTPUT_SAMPLE_RATE: Option<u32> = None;
+
+/// The duration of audio in each message, rounded to a whole number of frames. MPEG audio frames
+/// are around 25 ms long and FLAC frames around 100 ms, batching them cuts the per-message
+/// overhead.
+const CHUNK_DURATION: u64 = 100 * NS_PER_S / 1000;
+
+/// Whether to also publish each channel of multichannel audio as mono audio on its own topic,
+/// `/audio/left` and `/audio/right` for stereo.
+const SPLIT_CHANNELS: bool = true;
+
+/// Whether to publish the short-term loudness of the audio on `/audio/loudness`. This runs a
+/// filter over every sample, and seeking decodes an extra `LOUDNESS_WINDOW` of audio to fill the
+/// measurement window.
+const PUBLISH_LOUDNESS: bool = true;
+
+/// The window short-term loudness is measured over, 3 s as defined by EBU R 128.
+const LOUDNESS_WINDOW: u64 = 3 * NS_PER_S;
+
+/// Whether to publish a spectrogram of the audio on `/audio/spectrogram`.
+const PUBLISH_SPECTROGRAM: bool = true;
+
+/// The spectrogram is published as grayscale images scrolling from right to left. Each column of
+/// the image is the spectrum of `window` samples, so the frequency resolution is the sample rate
+/// divided by `window`. A new image is published every `step` columns.
+const SPECTROGRAM: SpectrogramConfig = SpectrogramConfig {
+    window: 1024,
+    max_frequency: 8000.0,
+    columns: 256,
+    step: 16,
+    min_db: -100.0,
+    max_db: 0.0,
+};
+
+/// The audio file formats the loader reads. The format is detected from the start of the file,
+/// not the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileType {
+    Mp3,
+    Wav,
+    Flac,
+    Ogg,
+}
+
+impl FileType {
+    fn detect(path: &str) -> std::io::Result<Self> {
+        let mut reader = reader::open(path);
+        let mut head = [0u8; 12];
+        let mut len = 0;
+        while len < head.len() {
+            match Read::read(&mut reader, &mut head[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        let head = &head[..len];
+        if head.len() == 12
+            && matches!(&head[..4], b"RIFF" | b"RF64" | b"BW64")
+            && &head[8..] == b"WAVE"
+        {
+            return Ok(Self::Wav);
+        }
+        if head.starts_with(b"OggS") {
+            return Ok(Self::Ogg);
+        }
+        // FLAC files can start with an ID3v2 tag, like MP3 files
+        let tag_len = frame::id3v2_len(head);
+        Seek::seek(&mut reader, SeekFrom::Start(tag_len))?;
+        let mut magic = [0u8; 4];
+        if reader.read_exact(&mut magic).is_ok() && &magic == b"fLaC" {
+            return Ok(Self::Flac);
+        }
+        Ok(Self::Mp3)
+    }
+}
+
+/// How to decode the audio of a file, once it has been indexed.
+#[derive(Debug, Clone)]
+enum Codec {
+    /// MPEG audio, with the layout of the stream if it has a constant bitrate
+    Mp3(Option<ConstantBitrate>),
+    Wav(WavFormat),
+    Flac(StreamInfo),
+    Ogg(VorbisStream),
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::Mp3(None)
+    }
+}
+
+impl Codec {
+    /// The number of frames decoded and discarded before the requested start time when seeking.
+    fn warmup_frames(&self) -> u64 {
+        match self {
+            Self::Mp3(_) => WARMUP_FRAMES,
+            // Lossless frames are decoded independently
+            Self::Wav(_) | Self::Flac(_) => 0,
+            // The Vorbis decoder starts a page early by itself
+            Self::Ogg(_) => 0,
+        }
+    }
+}
+
+/// Decodes the audio of a file one frame at a time, from the frame it was positioned at.
+trait AudioDecoder {
+    /// Decode the next frame into `samples` as interleaved samples, returning the frame's format,
+    /// or None at the end of the audio.
+    fn decode(&mut self, samples: &mut Vec<f32>) -> anyhow::Result<Option<FrameFormat>>;
+
+    /// The number of bytes skipped because no audio could be decoded from them.
+    fn skipped_bytes(&self) -> u64 {
+        0
+    }
+}
+
+/// The format of a decoded frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameFormat {
+    channels: usize,
+    sample_rate: u32,
+}
+
 #[derive(Default)]
-struct Mp3DataLoader {
+struct AudioDataLoader {
     path: String,
-    content: Arc<Vec<u8>>,
-    /// Index of timestamp to byte offset
-    indexes: BTreeMap<u64, usize>,
+    codec: Codec,
+    /// Index of time since the start of the audio to byte offset
+    indexes: TimeIndex<u64>,
+    /// The recording start time in nanoseconds since the Unix epoch
+    start_time: u64,
+    /// The duration of the audio in nanoseconds
+    duration: u64,
+    /// The byte offset of the end of the audio data
+    audio_end: u64,
+    /// The duration of each frame in nanoseconds
+    frame_duration: u64,
+    /// The number of frames in each message
+    chunk_frames: u64,
+    sample_rate: u32,
+    /// The number of audio channels
+    channels: u8,
+    /// Whether the sample rate or number of channels changes partway through the audio
+    format_changes: bool,
     channel_id: u16,
+    /// The mono channel for each audio channel, when they're split
+    split_channel_ids: Vec<u16>,
+    envelope_channel_id: u16,
+    loudness_channel_id: Option<u16>,
+    spectrogram_channel_id: Option<u16>,
+    /// Messages built from the file's tags, published at the start of the file
+    metadata: Vec<Message>,
+    /// Problems found while indexing the file, shown in Foxglove's problems panel
+    problems: Vec<Problem>,
 }
 
-impl DataLoader for Mp3DataLoader {
-    type MessageIterator = Mp3MessageIterator;
+impl DataLoader for AudioDataLoader {
+    type MessageIterator = AudioMessageIterator;
     type Error = anyhow::Error;
 
     fn new(args: DataLoaderArgs) -> Self {
@@ -37,34 +237,90 @@ impl DataLoader for Mp3DataLoader {
     }
 
     fn initialize(&mut self) -> Result<Initialization, Self::Error> {
-        let mut reader = reader::open(&self.path);
-        let size = reader.size();
-        let mut buf = vec![0u8; size as usize];
-        reader
-            .read_exact(&mut buf)
-            .context("failed reading MP3 data")?;
-        let mut decoder = nanomp3::Decoder::new();
-        let mut message_count: u64 = 0;
-        let mut pos: usize = 0;
-        let mut ts: u64 = 0;
-        let mut pcm = [0f32; nanomp3::MAX_SAMPLES_PER_FRAME];
-        while pos < buf.len() {
-            let (consumed, frame_info) = decoder.decode(&buf[pos..], &mut pcm);
-            if let Some(frame_info) = frame_info {
-                self.indexes.insert(ts, pos);
-                ts += len_ns(&frame_info);
-                message_count += 1;
-            }
-            pos += consumed;
-        }
-        self.content = Arc::new(buf);
-        let mut init = Initialization::builder().start_time(0).end_time(ts);
-        let channel = init
-            .add_encode::<foxglove::schemas::RawAudio>()?
-            .add_channel("/audio")
-            .message_count(message_count);
+        let (frame_count, tag) = match FileType::detect(&self.path)? {
+            FileType::Mp3 => self.index_mp3()?,
+            FileType::Wav => self.index_wav()?,
+            FileType::Flac => self.index_flac()?,
+            FileType::Ogg => self.index_ogg()?,
+        };
+        self.start_time = start_time(&self.path, &tag);
+        self.chunk_frames = chunk_frames(self.frame_duration);
+        // The iterator starts a new chunk wherever the format changes, and the spectrogram starts
+        // over with a new sample rate, so their message counts are only known for a single format
+        let message_count = (!self.format_changes).then(|| frame_count.div_ceil(self.chunk_frames));
+        let mut init = Initialization::builder()
+            .start_time(self.start_time)
+            .end_time(self.start_time + self.duration);
+        let audio_schema = init.add_encode::<foxglove::schemas::RawAudio>()?;
+        let channel = with_message_count(audio_schema.add_channel("/audio"), message_count);
         self.channel_id = channel.id();
+        if SPLIT_CHANNELS && self.channels > 1 {
+            for index in 0..self.channels {
+                let channel = with_message_count(
+                    audio_schema.add_channel(&split_topic(index, self.channels)),
+                    message_count,
+                );
+                self.split_channel_ids.push(channel.id());
+            }
+        }
+        let channel = with_message_count(
+            init.add_encode::<AudioEnvelope>()?
+                .add_channel("/audio/envelope"),
+            message_count,
+        );
+        self.envelope_channel_id = channel.id();
+        if PUBLISH_LOUDNESS {
+            let channel = with_message_count(
+                init.add_encode::<AudioLoudness>()?
+                    .add_channel("/audio/loudness"),
+                message_count,
+            );
+            self.loudness_channel_id = Some(channel.id());
+        }
+        if PUBLISH_SPECTROGRAM {
+            let sample_rate = OUTPUT_SAMPLE_RATE.unwrap_or(self.sample_rate);
+            // The duration is rounded down to whole nanoseconds, round it back to whole samples
+            let samples = (self.duration as u128 * sample_rate as u128 + NS_PER_S as u128 / 2)
+                / NS_PER_S as u128;
+            let images = samples / (SPECTROGRAM.window * SPECTROGRAM.step) as u128;
+            let channel = with_message_count(
+                init.add_encode::<foxglove::schemas::RawImage>()?
+                    .add_channel("/audio/spectrogram"),
+                (!self.format_changes).then_some(images as u64),
+            );
+            self.spectrogram_channel_id = Some(channel.id());
+        }
 
+        if !tag.is_empty() {
+            let channel = init
+                .add_encode::<AudioMetadata>()?
+                .add_channel("/audio/metadata")
+                .message_count(1);
+            let metadata = AudioMetadata::from_tag(&tag);
+            let msg = to_message(channel.id(), self.start_time, &metadata)?;
+            self.metadata.push(msg);
+        }
+        if let Some((picture, format)) = tag
+            .picture
+            .and_then(|picture| picture.format().map(|format| (picture, format)))
+        {
+            let channel = init
+                .add_encode::<foxglove::schemas::CompressedImage>()?
+                .add_channel("/audio/cover")
+                .message_count(1);
+            let image = foxglove::schemas::CompressedImage {
+                timestamp: Some(to_timestamp(self.start_time)),
+                frame_id: String::new(),
+                data: picture.data.into(),
+                format: format.into(),
+            };
+            let msg = to_message(channel.id(), self.start_time, &image)?;
+            self.metadata.push(msg);
+        }
+
+        for problem in self.problems.drain(..) {
+            init = init.add_problem(problem);
+        }
         Ok(init.build())
     }
 
@@ -72,104 +328,1029 @@ impl DataLoader for Mp3DataLoader {
         &mut self,
         args: MessageIteratorArgs,
     ) -> Result<Self::MessageIterator, Self::Error> {
-        let file_end_time = *self.indexes.last_entry().unwrap().key();
-        let start_time = args.start_time.unwrap_or(0);
-        if start_time > file_end_time {
-            return Ok(Mp3MessageIterator::empty());
-        }
-        let end_time = args.end_time.unwrap_or(file_end_time);
-        let mut range = self.indexes.range(start_time..=end_time);
-        let Some((&cur_timestamp, &cur_pos)) = range.next() else {
-            return Ok(Mp3MessageIterator::empty());
+        let start_time = args.start_time.unwrap_or(self.start_time);
+        if start_time > self.start_time + self.duration {
+            return Ok(AudioMessageIterator::empty());
+        }
+        let end_time = args.end_time.unwrap_or(self.start_time + self.duration);
+        // The metadata messages are logged at the start time, so they're only part of iterators
+        // starting at the beginning of the file.
+        let metadata = self
+            .metadata
+            .iter()
+            .filter(|msg| start_time <= msg.log_time && msg.log_time <= end_time)
+            .filter(|msg| args.channels.contains(&msg.channel_id))
+            .cloned()
+            .collect();
+        let channel_id = args
+            .channels
+            .contains(&self.channel_id)
+            .then_some(self.channel_id);
+        let split_channel_ids: Vec<Option<u16>> = self
+            .split_channel_ids
+            .iter()
+            .map(|id| args.channels.contains(id).then_some(*id))
+            .collect();
+        let envelope_channel_id = args
+            .channels
+            .contains(&self.envelope_channel_id)
+            .then_some(self.envelope_channel_id);
+        let loudness = self
+            .loudness_channel_id
+            .filter(|id| args.channels.contains(id))
+            .map(|id| (id, LoudnessMeter::new(LOUDNESS_WINDOW)));
+        let spectrogram = self
+            .spectrogram_channel_id
+            .filter(|id| args.channels.contains(id))
+            .map(|id| (id, Spectrogram::new(SPECTROGRAM)));
+        // Without any channels built from the audio there's nothing to decode
+        if channel_id.is_none()
+            && split_channel_ids.iter().all(Option::is_none)
+            && envelope_channel_id.is_none()
+            && loudness.is_none()
+            && spectrogram.is_none()
+        {
+            return Ok(AudioMessageIterator {
+                pending: metadata,
+                ..AudioMessageIterator::empty()
+            });
+        }
+        // Start from the closest indexed point at least the codec's warm-up frames before the start
+        // time. The frames before the start time are decoded to warm up the decoder, then discarded
+        // by the iterator. When loudness is measured the warm-up also fills the measurement window.
+        let mut warmup = self.codec.warmup_frames() * self.frame_duration;
+        if loudness.is_some() {
+            warmup = warmup.max(LOUDNESS_WINDOW);
+        }
+        let warmup_time = start_time
+            .saturating_sub(self.start_time)
+            .saturating_sub(warmup);
+        let start = match &self.codec {
+            // Any frame of a constant bitrate stream can be found without an index
+            Codec::Mp3(Some(stream)) => stream
+                .find_frame(
+                    reader::open(&self.path),
+                    self.audio_end,
+                    warmup_time / self.frame_duration,
+                )
+                .context("failed reading MP3 data")?
+                .map(|(index, pos)| (index * self.frame_duration, pos)),
+            _ => self
+                .indexes
+                .latest(warmup_time)
+                .map(|(offset, &pos)| (offset, pos)),
+        };
+        let Some((offset, cur_pos)) = start else {
+            return Ok(AudioMessageIterator {
+                pending: metadata,
+                ..AudioMessageIterator::empty()
+            });
+        };
+        let reader = reader::open(&self.path);
+        reader.seek(cur_pos);
+        // Stop at the end of the audio, before any trailing chunks or tags
+        let reader = reader.take(self.audio_end.saturating_sub(cur_pos));
+        let decoder: Box<dyn AudioDecoder> = match &self.codec {
+            Codec::Mp3(_) => Box::new(FrameDecoder::new(reader)),
+            Codec::Wav(format) => Box::new(WavDecoder::new(reader, *format)),
+            Codec::Flac(stream) => Box::new(FlacDecoder::new(reader, stream)),
+            // Every indexed page but the first audio page is one to prime the decoder with
+            Codec::Ogg(stream) => Box::new(OggDecoder::new(reader, stream, offset > 0)?),
         };
-        Ok(Mp3MessageIterator {
-            decoder: nanomp3::Decoder::new(),
-            content: self.content.clone(),
-            channel_id: self.channel_id,
-            cur_pos,
-            cur_timestamp,
+        Ok(AudioMessageIterator {
+            decoder,
+            done: false,
+            pcm: PcmEncoder::new(OUTPUT_FORMAT, DITHER),
+            resampler: OUTPUT_SAMPLE_RATE.map(Resampler::new),
+            channel_id,
+            audio_start: self.start_time,
+            frame_duration: self.frame_duration,
+            chunk_frames: self.chunk_frames,
+            cur_timestamp: self.start_time + offset,
+            start: start_time,
             until: end_time,
-            last_encoded_message: Vec::new(),
+            split_channel_ids,
+            envelope_channel_id,
+            loudness,
+            spectrogram,
+            chunk: None,
+            pending: metadata,
         })
     }
+
+    fn get_backfill(&mut self, args: BackfillArgs) -> Result<Vec<Message>, Self::Error> {
+        let mut backfill: Vec<Message> = self
+            .metadata
+            .iter()
+            .filter(|msg| msg.log_time <= args.time && args.channels.contains(&msg.channel_id))
+            .cloned()
+            .collect();
+        // The audio and the levels measured from it have a message for every chunk of frames. The
+        // spectrogram is built up over many chunks, so it isn't backfilled.
+        let frame_channels: Vec<u16> = [
+            Some(self.channel_id),
+            Some(self.envelope_channel_id),
+            self.loudness_channel_id,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.split_channel_ids.iter().copied())
+        .filter(|id| args.channels.contains(id))
+        .collect();
+        if !frame_channels.is_empty() && args.time >= self.start_time {
+            // The chunk covering the backfill time is the last one starting less than a chunk
+            // duration before it. Decoding it goes through the iterator so the decoder is warmed
+            // up the same way as during playback.
+            let chunk_duration = self.chunk_frames * self.frame_duration;
+            let start_time = args
+                .time
+                .saturating_sub(chunk_duration.saturating_sub(1))
+                .max(self.start_time);
+            let mut iter = self.create_iter(MessageIteratorArgs {
+                start_time: Some(start_time),
+                end_time: Some(args.time),
+                channels: frame_channels,
+            })?;
+            let mut latest = BTreeMap::new();
+            while let Some(msg) = iter.next() {
+                let msg = msg?;
+                latest.insert(msg.channel_id, msg);
+            }
+            backfill.extend(latest.into_values());
+        }
+        Ok(backfill)
+    }
 }
 
-fn len_ns(frame_info: &nanomp3::FrameInfo) -> u64 {
-    (frame_info.samples_produced as u64 * NS_PER_S) / (frame_info.sample_rate as u64)
+impl AudioDataLoader {
+    /// Index the frames of an MP3 file, returning the number of frames and the file's tags.
+    fn index_mp3(&mut self) -> anyhow::Result<(u64, Id3Tag)> {
+        // The tags are only metadata, so the audio is still loaded without them
+        let tag = read_id3(&self.path).unwrap_or_else(|err| {
+            self.problems
+                .push(Problem::warn(format!("Failed reading ID3 tags: {err}")));
+            Id3Tag::default()
+        });
+        let mut frames = frame::FrameScanner::new(reader::open(&self.path))
+            .context("failed reading MP3 data")?;
+        let info_tag = frames.read_info_tag().context("failed reading MP3 data")?;
+        self.audio_end = frames.end();
+        let frame_count = match info_tag {
+            // A constant bitrate file doesn't need to be scanned: the info tag has the frame count,
+            // and the iterator finds the frame to start from by its offset. Variable bitrate files
+            // are scanned, as their seek tables only give rough offsets, not the index of the
+            // frame there, so seeking with them would log the audio at the wrong time.
+            Some((
+                offset,
+                header,
+                InfoTag {
+                    frames: Some(frames),
+                    bytes: Some(bytes),
+                    constant_bitrate: true,
+                    ..
+                },
+            )) if frames > 0 => {
+                let first_frame = offset + header.frame_len() as u64;
+                self.frame_duration = header.duration_ns();
+                self.sample_rate = header.sample_rate;
+                self.channels = header.channels;
+                self.duration = frames as u64 * header.duration_ns();
+                self.codec = Codec::Mp3(Some(ConstantBitrate {
+                    first_frame,
+                    frame_len: (bytes as u64).saturating_sub(header.frame_len() as u64) as f64
+                        / frames as f64,
+                }));
+                frames as u64
+            }
+            // Otherwise read every frame header to build the index. The audio data is skipped
+            // over and decoded later by the message iterator.
+            _ => {
+                let mut frame_count: u64 = 0;
+                let mut last_format = None;
+                while let Some((pos, header)) =
+                    frames.next_frame().context("failed reading MP3 data")?
+                {
+                    self.indexes.insert(self.duration, pos);
+                    let format = (header.sample_rate, header.channels);
+                    self.format_changes |= last_format.is_some_and(|last| last != format);
+                    last_format = Some(format);
+                    self.frame_duration = header.duration_ns();
+                    self.sample_rate = header.sample_rate;
+                    self.channels = self.channels.max(header.channels);
+                    self.duration += header.duration_ns();
+                    frame_count += 1;
+                }
+                frame_count
+            }
+        };
+        let (skipped, truncated) = (frames.skipped_bytes(), frames.truncated_frames());
+        if skipped > 0 {
+            self.problems.push(Problem::warn(format!(
+                "Skipped {skipped} bytes of invalid MP3 data"
+            )));
+        }
+        if truncated > 0 {
+            self.problems.push(Problem::warn(format!(
+                "Skipped {truncated} truncated MP3 frames"
+            )));
+        }
+        Ok((frame_count, tag))
+    }
+
+    /// Index a WAV file, returning the number of frames. WAV files aren't tagged.
+    fn index_wav(&mut self) -> anyhow::Result<(u64, Id3Tag)> {
+        let format =
+            WavFormat::read(reader::open(&self.path)).context("failed reading WAV header")?;
+        let samples = format.sample_count();
+        self.sample_rate = format.sample_rate;
+        self.channels = u8::try_from(format.channels).context("too many channels")?;
+        self.frame_duration = wav::FRAME_LEN * NS_PER_S / format.sample_rate as u64;
+        self.duration = samples_to_ns(samples, format.sample_rate);
+        self.audio_end = format.data_offset + format.data_len;
+        // The offset of every frame can be computed, so only the frames starting a chunk are
+        // indexed to keep the index small for long recordings.
+        let frame_count = samples.div_ceil(wav::FRAME_LEN);
+        let chunk_frames = chunk_frames(self.frame_duration);
+        for frame in (0..frame_count).step_by(chunk_frames as usize) {
+            let offset = format.offset_of(frame * wav::FRAME_LEN);
+            self.indexes.insert(frame * self.frame_duration, offset);
+        }
+        self.codec = Codec::Wav(format);
+        Ok((frame_count, Id3Tag::default()))
+    }
+
+    /// Index the frames of a FLAC file, returning the number of frames and the file's tags.
+    fn index_flac(&mut self) -> anyhow::Result<(u64, Id3Tag)> {
+        let info =
+            FlacInfo::read(reader::open(&self.path)).context("failed reading FLAC metadata")?;
+        let stream = info.stream;
+        // Without a seek table every frame header has to be found
+        let frames = if info.seek_points.is_empty() {
+            flac::scan_frames(reader::open(&self.path), &info)
+                .context("failed reading FLAC frames")?
+        } else {
+            info.seek_points
+        };
+        self.indexes.insert(0, info.audio_start);
+        for &(sample, offset) in &frames {
+            self.indexes
+                .insert(samples_to_ns(sample, stream.sample_rate), offset);
+        }
+        // The stream info can leave the length unknown, when it was written by a stream encoder
+        let samples = match stream.total_samples {
+            0 => frames
+                .last()
+                .map_or(0, |(sample, _)| sample + stream.max_block_size as u64),
+            samples => samples,
+        };
+        self.sample_rate = stream.sample_rate;
+        self.channels = stream.channels;
+        self.frame_duration = samples_to_ns(stream.max_block_size as u64, stream.sample_rate);
+        self.duration = samples_to_ns(samples, stream.sample_rate);
+        self.audio_end = frame::audio_end(reader::open(&self.path))?;
+        self.codec = Codec::Flac(stream);
+        Ok((samples.div_ceil(stream.max_block_size as u64), info.tag))
+    }
+
+    /// Index the pages of an Ogg Vorbis file, returning the number of frames and the file's tags.
+    /// Vorbis packets vary in length, so a frame is taken to be the audio of a long block.
+    fn index_ogg(&mut self) -> anyhow::Result<(u64, Id3Tag)> {
+        let info = OggInfo::read(reader::open(&self.path)).context("failed reading Ogg file")?;
+        let stream = &info.stream;
+        // Decoding starts a page before the indexed time, so it lands within a page of any time
+        self.indexes.insert(0, stream.audio_start);
+        for &(granule, offset) in &info.pages {
+            self.indexes
+                .insert(samples_to_ns(granule, stream.sample_rate), offset);
+        }
+        let frame_len = stream.long_block_size as u64 / 2;
+        self.sample_rate = stream.sample_rate;
+        self.channels = stream.channels;
+        self.frame_duration = samples_to_ns(frame_len, stream.sample_rate);
+        self.duration = samples_to_ns(info.total_samples, stream.sample_rate);
+        self.audio_end = info.audio_end;
+        if info.skipped_bytes > 0 {
+            let skipped = info.skipped_bytes;
+            self.problems.push(Problem::warn(format!(
+                "Skipped {skipped} bytes of invalid Ogg data"
+            )));
+        }
+        self.codec = Codec::Ogg(info.stream);
+        Ok((info.total_samples.div_ceil(frame_len), info.tag))
+    }
 }
 
-struct Mp3MessageIterator {
-    decoder: nanomp3::Decoder,
-    content: Arc<Vec<u8>>,
-    channel_id: u16,
-    cur_pos: usize,
+/// Read the ID3v2 tag at the start of the file, filling in missing fields from the ID3v1 tag at
+/// the end of the file.
+fn read_id3(path: &str) -> anyhow::Result<Id3Tag> {
+    let mut reader = reader::open(path);
+    let mut tag = Id3Tag::read_id3v2(&mut reader)?.unwrap_or_default();
+    let len = Seek::seek(&mut reader, SeekFrom::End(0))?;
+    if len >= ID3V1_LEN as u64 {
+        Seek::seek(&mut reader, SeekFrom::Start(len - ID3V1_LEN as u64))?;
+        let mut buf = [0u8; ID3V1_LEN];
+        reader.read_exact(&mut buf)?;
+        if let Some(id3v1) = Id3Tag::parse_id3v1(&buf) {
+            tag.merge(id3v1);
+        }
+    }
+    Ok(tag)
+}
+
+/// The recording's metadata from its ID3 tags or Vorbis comments, published on `/audio/metadata`.
+/// Fields missing from the tags are left empty.
+#[derive(Debug, Clone, foxglove::Encode)]
+struct AudioMetadata {
+    title: String,
+    artist: String,
+    album: String,
+    comment: String,
+    /// The recording time from the tags as an ISO 8601 string
+    recording_time: String,
+    /// User defined text frames (`TXXX`), or other Vorbis comments
+    user_text: Vec<MetadataEntry>,
+}
+
+#[derive(Debug, Clone, foxglove::Encode)]
+struct MetadataEntry {
+    key: String,
+    value: String,
+}
+
+impl AudioMetadata {
+    fn from_tag(tag: &Id3Tag) -> Self {
+        Self {
+            title: tag.title.clone().unwrap_or_default(),
+            artist: tag.artist.clone().unwrap_or_default(),
+            album: tag.album.clone().unwrap_or_default(),
+            comment: tag.comment.clone().unwrap_or_default(),
+            recording_time: tag.recording_time.clone().unwrap_or_default(),
+            user_text: tag
+                .user_text
+                .iter()
+                .map(|(key, value)| MetadataEntry {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The level of each chunk of audio, published on `/audio/envelope`.
+#[derive(Debug, Clone, foxglove::Encode)]
+struct AudioEnvelope {
+    /// The RMS level across all channels, as a fraction of full scale
+    rms: f64,
+    /// The largest absolute sample value across all channels, as a fraction of full scale
+    peak: f64,
+}
+
+/// The short-term loudness of the audio, published on `/audio/loudness`.
+#[derive(Debug, Clone, foxglove::Encode)]
+struct AudioLoudness {
+    /// The loudness of the last `LOUDNESS_WINDOW` of audio in LUFS
+    lufs: f64,
+}
+
+/// The topic for one channel of split audio, counting channels from 0.
+fn split_topic(index: u8, channels: u8) -> String {
+    match (channels, index) {
+        (2, 0) => "/audio/left".into(),
+        (2, 1) => "/audio/right".into(),
+        _ => format!("/audio/ch{}", index + 1),
+    }
+}
+
+/// The recording start time, from the [`START_TIME_FIELD`] tag, the recording time in the tags or
+/// the filename, in that order. Only timestamps with a time of day are used.
+fn start_time(path: &str, tag: &Id3Tag) -> u64 {
+    tag.user_text
+        .get(START_TIME_FIELD)
+        .and_then(|value| {
+            timestamp::parse_unix_seconds(value).or_else(|| timestamp::parse_timestamp(value))
+        })
+        .or_else(|| {
+            tag.recording_time
+                .as_deref()
+                .and_then(timestamp::parse_timestamp)
+        })
+        .or_else(|| timestamp::find_in_filename(path))
+        .unwrap_or(FALLBACK_START_TIME)
+}
+
+fn to_timestamp(ns: u64) -> foxglove::schemas::Timestamp {
+    foxglove::schemas::Timestamp::new((ns / NS_PER_S) as u32, (ns % NS_PER_S) as u32)
+}
+
+fn to_message(channel_id: u16, log_time: u64, msg: &impl Encode) -> anyhow::Result<Message> {
+    let mut data = Vec::with_capacity(msg.encoded_len().unwrap_or(0));
+    msg.encode(&mut data)
+        .map_err(|err| anyhow::anyhow!("failed to encode message: {err}"))?;
+    Ok(Message {
+        channel_id,
+        log_time,
+        publish_time: log_time,
+        data,
+    })
+}
+
+/// The duration of `samples` samples per channel in nanoseconds.
+fn samples_to_ns(samples: u64, sample_rate: u32) -> u64 {
+    (samples as u128 * NS_PER_S as u128 / sample_rate as u128) as u64
+}
+
+/// Set the channel's message count, if it's known.
+fn with_message_count(channel: LinkedChannel, message_count: Option<u64>) -> LinkedChannel {
+    match message_count {
+        Some(message_count) => channel.message_count(message_count),
+        None => channel,
+    }
+}
+
+/// The number of frames in each message, the frames closest to [`CHUNK_DURATION`].
+fn chunk_frames(frame_duration: u64) -> u64 {
+    ((CHUNK_DURATION + frame_duration / 2) / frame_duration.max(1)).max(1)
+}
+
+struct AudioMessageIterator {
+    decoder: Box<dyn AudioDecoder>,
+    /// Whether decoding has reached the end of the file or the end time
+    done: bool,
+    pcm: PcmEncoder,
+    resampler: Option<Resampler>,
+    /// The channel for the audio, if it was requested
+    channel_id: Option<u16>,
+    /// The start time of the audio, which chunks are aligned to
+    audio_start: u64,
+    frame_duration: u64,
+    /// The number of frames in each chunk
+    chunk_frames: u64,
     cur_timestamp: u64,
+    start: u64,
     until: u64,
-    last_encoded_message: Vec<u8>,
+    /// The mono channel for each audio channel, if it was requested
+    split_channel_ids: Vec<Option<u16>>,
+    envelope_channel_id: Option<u16>,
+    /// The loudness channel and meter, if the channel was requested
+    loudness: Option<(u16, LoudnessMeter)>,
+    spectrogram: Option<(u16, Spectrogram)>,
+    /// Decoded frames that haven't been emitted yet
+    chunk: Option<Chunk>,
+    /// Messages still to be emitted before decoding more audio
+    pending: VecDeque<Message>,
+}
+
+/// Consecutive decoded frames emitted together as one message.
+struct Chunk {
+    log_time: u64,
+    /// Interleaved samples
+    samples: Vec<f32>,
+    channels: usize,
+    sample_rate: u32,
+    /// The loudness at the end of the chunk
+    loudness: Option<f64>,
+    /// Spectrogram images completed during the chunk, with the time of the frame completing them
+    images: Vec<(u64, spectrogram::Image)>,
 }
 
-impl Mp3MessageIterator {
+impl AudioMessageIterator {
     fn empty() -> Self {
         Self {
-            decoder: nanomp3::Decoder::new(),
-            content: Default::default(),
-            channel_id: 0,
-            cur_pos: 0,
+            decoder: Box::new(FrameDecoder::new(Cursor::new([]))),
+            done: true,
+            pcm: PcmEncoder::new(OUTPUT_FORMAT, DITHER),
+            resampler: None,
+            channel_id: None,
+            audio_start: 0,
+            frame_duration: 0,
+            chunk_frames: 1,
             cur_timestamp: 1,
+            start: 0,
             until: 0,
-            last_encoded_message: Vec::new(),
+            split_channel_ids: Vec::new(),
+            envelope_channel_id: None,
+            loudness: None,
+            spectrogram: None,
+            chunk: None,
+            pending: VecDeque::new(),
         }
     }
-}
 
-impl MessageIterator for Mp3MessageIterator {
-    type Error = anyhow::Error;
+    /// Decode the next frame and add it to the current chunk, emitting the chunk first if the
+    /// frame starts a new one. Returns false once there are no more frames to decode.
+    fn decode_frame(&mut self) -> anyhow::Result<bool> {
+        if self.done {
+            return Ok(false);
+        }
+        let mut samples = Vec::with_capacity(nanomp3::MAX_SAMPLES_PER_FRAME);
+        let format = match self.decoder.decode(&mut samples)? {
+            Some(format) if self.cur_timestamp <= self.until => format,
+            _ => {
+                self.done = true;
+                let skipped = self.decoder.skipped_bytes();
+                if skipped > 0 {
+                    console::log(&format![
+                        "Skipped {skipped} bytes of undecodable audio data"
+                    ]);
+                }
+                return Ok(false);
+            }
+        };
+        let channels = format.channels;
+        let valid = &samples[..];
+        let log_time = self.cur_timestamp;
+        self.cur_timestamp += samples_to_ns((samples.len() / channels) as u64, format.sample_rate);
+        // Everything downstream sees the resampled audio. The resampler is fed the warm-up
+        // frames, so it has the history it needs to interpolate the first emitted samples.
+        let resampled;
+        let (valid, sample_rate) = match &mut self.resampler {
+            Some(resampler) => {
+                resampled = resampler.process(valid, channels, format.sample_rate);
+                (&resampled[..], resampler.output_rate())
+            }
+            None => (valid, format.sample_rate),
+        };
+        // The loudness meter and spectrogram are fed the warm-up frames too, so they start
+        // out with some history
+        let loudness = self
+            .loudness
+            .as_mut()
+            .map(|(_, meter)| meter.push(valid, channels, sample_rate));
+        let image = self
+            .spectrogram
+            .as_mut()
+            .and_then(|(_, spectrogram)| spectrogram.push(valid, channels, sample_rate));
+        // Discard the warm-up frames decoded before the start time
+        if log_time < self.start {
+            return Ok(true);
+        }
 
-    fn next(&mut self) -> Option<Result<Message, Self::Error>> {
-        if self.cur_timestamp > self.until {
-            return None;
+        // Chunks start on multiples of the chunk duration from the start of the audio, so
+        // they're the same wherever playback starts. A frame with a different format can't be
+        // part of the same message either.
+        let frame_index =
+            (log_time - self.audio_start + self.frame_duration / 2) / self.frame_duration;
+        let format_changed = self
+            .chunk
+            .as_ref()
+            .is_some_and(|chunk| chunk.channels != channels || chunk.sample_rate != sample_rate);
+        if frame_index.is_multiple_of(self.chunk_frames) || format_changed {
+            self.flush()?;
         }
-        let mut samples = [0f32; nanomp3::MAX_SAMPLES_PER_FRAME];
-        while self.cur_pos < self.content.len() && self.cur_timestamp <= self.until {
-            let (consumed, frame_info) = self
-                .decoder
-                .decode(&self.content[self.cur_pos..], &mut samples);
-            self.cur_pos += consumed;
+        let chunk = self.chunk.get_or_insert_with(|| Chunk {
+            log_time,
+            samples: Vec::new(),
+            channels,
+            sample_rate,
+            loudness: None,
+            images: Vec::new(),
+        });
+        chunk.samples.extend_from_slice(valid);
+        chunk.loudness = loudness;
+        chunk.images.extend(image.map(|image| (log_time, image)));
+        Ok(true)
+    }
 
-            let Some(frame_info) = frame_info else {
+    /// Build a `RawAudio` message from interleaved samples.
+    fn raw_audio(
+        &mut self,
+        log_time: u64,
+        samples: &[f32],
+        channels: usize,
+        sample_rate: u32,
+    ) -> foxglove::schemas::RawAudio {
+        let mut data = vec![];
+        self.pcm.encode(samples, &mut data);
+        foxglove::schemas::RawAudio {
+            timestamp: Some(to_timestamp(log_time)),
+            format: self.pcm.format().name().into(),
+            data: data.into(),
+            number_of_channels: channels as u32,
+            sample_rate,
+        }
+    }
+
+    /// Queue the messages for the current chunk on the requested channels: the audio, followed by
+    /// the messages derived from it.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let Some(chunk) = self.chunk.take() else {
+            return Ok(());
+        };
+        let Chunk {
+            log_time,
+            samples,
+            channels,
+            sample_rate,
+            loudness,
+            images,
+        } = chunk;
+        if let Some(channel_id) = self.channel_id {
+            let audio = self.raw_audio(log_time, &samples, channels, sample_rate);
+            self.pending
+                .push_back(to_message(channel_id, log_time, &audio)?);
+        }
+
+        for index in 0..self.split_channel_ids.len().min(channels) {
+            let Some(channel_id) = self.split_channel_ids[index] else {
                 continue;
             };
-            let valid =
-                &samples[..frame_info.samples_produced * frame_info.channels.num() as usize];
-            let duration = len_ns(&frame_info);
-            let log_time = self.cur_timestamp;
-            self.cur_timestamp += duration;
-            let sec = (log_time / NS_PER_S) as u32;
-            let nsec = (log_time % NS_PER_S) as u32;
-            let msg = foxglove::schemas::RawAudio {
-                timestamp: Some(foxglove::schemas::Timestamp::new(sec, nsec)),
-                format: "pcm-s16".into(),
-                data: valid
-                    .iter()
-                    .flat_map(|&i| ((i * i16::MAX as f32) as i16).to_le_bytes())
-                    .collect(),
-                number_of_channels: frame_info.channels.num() as u32,
-                sample_rate: frame_info.sample_rate,
-            };
-            self.last_encoded_message.clear();
-            if let Err(err) = msg.encode(&mut self.last_encoded_message) {
-                return Some(Err(err.into()));
-            };
+            let mono: Vec<f32> = samples
+                .iter()
+                .skip(index)
+                .step_by(channels)
+                .copied()
+                .collect();
+            let audio = self.raw_audio(log_time, &mono, 1, sample_rate);
+            self.pending
+                .push_back(to_message(channel_id, log_time, &audio)?);
+        }
+        if let Some(channel_id) = self.envelope_channel_id {
+            let Envelope { rms, peak } = Envelope::measure(&samples);
+            let envelope = AudioEnvelope { rms, peak };
+            self.pending
+                .push_back(to_message(channel_id, log_time, &envelope)?);
+        }
+        if let (Some((channel_id, _)), Some(lufs)) = (&self.loudness, loudness) {
+            let loudness = AudioLoudness { lufs };
+            self.pending
+                .push_back(to_message(*channel_id, log_time, &loudness)?);
+        }
+        if let Some((channel_id, _)) = &self.spectrogram {
+            let channel_id = *channel_id;
+            for (log_time, image) in images {
+                let image = foxglove::schemas::RawImage {
+                    timestamp: Some(to_timestamp(log_time)),
+                    frame_id: String::new(),
+                    width: image.width,
+                    height: image.height,
+                    encoding: "mono8".into(),
+                    step: image.width,
+                    data: image.data.into(),
+                };
+                self.pending
+                    .push_back(to_message(channel_id, log_time, &image)?);
+            }
+        }
+        Ok(())
+    }
+}
 
-            return Some(Ok(Message {
-                channel_id: self.channel_id,
-                log_time,
-                publish_time: log_time,
-                data: self.last_encoded_message.clone(),
-            }));
+impl MessageIterator for AudioMessageIterator {
+    type Error = anyhow::Error;
+
+    fn next(&mut self) -> Option<Result<Message, Self::Error>> {
+        loop {
+            if let Some(msg) = self.pending.pop_front() {
+                return Some(Ok(msg));
+            }
+            match self.decode_frame() {
+                Ok(true) => (),
+                // Emit the last, possibly partial, chunk
+                Ok(false) if self.chunk.is_some() => {
+                    if let Err(err) = self.flush() {
+                        return Some(Err(err));
+                    }
+                }
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
         }
-        None
     }
 }
 
-foxglove_data_loader::export!(Mp3DataLoader);
+foxglove_data_loader::export!(AudioDataLoader);
+
+#[cfg(test)]
+mod tests {
+    use data_loader_utils::{
+        conformance,
+        harness::{Harness, count_by_channel},
+    };
+
+    use super::*;
+
+    /// 40 frames of silent mono MPEG-1 Layer III audio at 44.1 kHz, tagged with a title and a
+    /// recording start time
+    const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../sample.mp3");
+    const START_TIME: u64 = 1_740_830_400 * NS_PER_S;
+    const FRAME_DURATION: u64 = 1152 * NS_PER_S / 44100;
+
+    fn encode(channel_id: u16, log_time: u64, msg: &impl Encode) -> Vec<u8> {
+        to_message(channel_id, log_time, msg).unwrap().data
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::assert_conforms::<AudioDataLoader>(&[SAMPLE]);
+    }
+
+    /// A 16 bit stereo WAV file of `samples` samples per channel at 8 kHz.
+    fn wav_file(samples: usize) -> Vec<u8> {
+        let data: Vec<u8> = (0..samples * 2)
+            .flat_map(|i| ((i % 200) as i16 * 100).to_le_bytes())
+            .collect();
+        let mut file = b"RIFF".to_vec();
+        file.extend((36 + data.len() as u32).to_le_bytes());
+        file.extend(b"WAVEfmt ");
+        file.extend(16u32.to_le_bytes());
+        // PCM, 2 channels, 8 kHz, 32000 bytes/s, 4 byte blocks, 16 bits
+        file.extend([1, 0, 2, 0]);
+        file.extend(8000u32.to_le_bytes());
+        file.extend(32000u32.to_le_bytes());
+        file.extend([4, 0, 16, 0]);
+        file.extend(b"data");
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn test_conformance_wav() {
+        // A partial frame at the end, with the channels split
+        reader::insert_file("conformance.wav", wav_file(3 * 8000 + 100));
+        conformance::assert_conforms::<AudioDataLoader>(&["conformance.wav"]);
+    }
+
+    #[test]
+    fn test_split_channels() {
+        reader::insert_file("stereo.wav", wav_file(8000));
+        let harness = Harness::<AudioDataLoader>::open(&["stereo.wav"]).unwrap();
+        let info = harness.info();
+        // The channels of the split audio share the schema of the full audio
+        let audio = info.channel("/audio").schema_id;
+        assert_eq!(info.channel("/audio/left").schema_id, audio);
+        assert_eq!(info.channel("/audio/right").schema_id, audio);
+        let raw_audio = info
+            .schemas
+            .iter()
+            .filter(|schema| schema.name == "foxglove.RawAudio")
+            .count();
+        assert_eq!(raw_audio, 1);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let harness = Harness::<AudioDataLoader>::open(&[SAMPLE]).unwrap();
+        let info = harness.info();
+        assert_eq!(info.start_time, START_TIME);
+        assert_eq!(info.end_time, START_TIME + 40 * FRAME_DURATION);
+        // Mono audio isn't split into channels, and the file has no cover art
+        let topics: Vec<&str> = info.channels.iter().map(|c| c.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "/audio",
+                "/audio/envelope",
+                "/audio/loudness",
+                "/audio/spectrogram",
+                "/audio/metadata"
+            ]
+        );
+        // Each message holds 4 frames
+        assert_eq!(info.channel("/audio").message_count, Some(10));
+        assert_eq!(info.channel("/audio/envelope").message_count, Some(10));
+        assert_eq!(info.channel("/audio/metadata").message_count, Some(1));
+    }
+
+    #[test]
+    fn test_read_audio() {
+        let mut harness = Harness::<AudioDataLoader>::open(&[SAMPLE]).unwrap();
+        let audio = harness.info().channel("/audio").id;
+        let envelope = harness.info().channel("/audio/envelope").id;
+        let metadata = harness.info().channel("/audio/metadata").id;
+        let messages = harness
+            .messages(MessageIteratorArgs {
+                start_time: None,
+                end_time: None,
+                channels: vec![audio, envelope, metadata],
+            })
+            .unwrap();
+        let counts = count_by_channel(&messages);
+        assert_eq!(counts[&audio], 10);
+        assert_eq!(counts[&envelope], 10);
+        assert_eq!(counts[&metadata], 1);
+
+        let tag = read_id3(SAMPLE).unwrap();
+        assert_eq!(tag.title.as_deref(), Some("Silence"));
+        let expected = encode(metadata, START_TIME, &AudioMetadata::from_tag(&tag));
+        assert_eq!(messages[0].channel_id, metadata);
+        assert_eq!(messages[0].data, expected);
+
+        let silence = AudioEnvelope {
+            rms: 0.0,
+            peak: 0.0,
+        };
+        for (index, message) in messages
+            .iter()
+            .filter(|message| message.channel_id == envelope)
+            .enumerate()
+        {
+            let log_time = START_TIME + index as u64 * 4 * FRAME_DURATION;
+            assert_eq!(message.log_time, log_time);
+            assert_eq!(message.data, encode(envelope, log_time, &silence));
+        }
+    }
+
+    #[test]
+    fn test_backfill() {
+        let mut harness = Harness::<AudioDataLoader>::open(&[SAMPLE]).unwrap();
+        let audio = harness.info().channel("/audio").id;
+        let envelope = harness.info().channel("/audio/envelope").id;
+        let metadata = harness.info().channel("/audio/metadata").id;
+        let time = START_TIME + 150_000_000;
+        let messages = harness
+            .backfill(time, vec![audio, envelope, metadata])
+            .unwrap();
+        let channels: Vec<u16> = messages.iter().map(|message| message.channel_id).collect();
+        assert_eq!(channels, vec![metadata, audio, envelope]);
+        // The chunk covering the time starts at the fifth frame
+        assert_eq!(messages[0].log_time, START_TIME);
+        assert_eq!(messages[1].log_time, START_TIME + 4 * FRAME_DURATION);
+        assert_eq!(messages[2].log_time, START_TIME + 4 * FRAME_DURATION);
+
+        assert!(
+            harness
+                .backfill(START_TIME - 1, vec![audio])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    /// A silent MPEG-1 Layer III frame at 44.1 kHz with the given bitrate index.
+    fn silent_frame(bitrate_index: u8, padding: bool) -> Vec<u8> {
+        let header = [0xff, 0xfb, bitrate_index << 4 | (padding as u8) << 1, 0xc0];
+        let len = frame::FrameHeader::parse(header).unwrap().frame_len();
+        let mut frame = vec![0u8; len];
+        frame[..frame::HEADER_LEN].copy_from_slice(&header);
+        frame
+    }
+
+    /// An MP3 file of `frames`, after a frame holding a Xing or Info tag with a table of contents.
+    fn tagged_file(marker: &[u8], frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = silent_frame(9, false);
+        let offsets: Vec<usize> = frames
+            .iter()
+            .scan(file.len(), |offset, frame| {
+                *offset += frame.len();
+                Some(*offset - frame.len())
+            })
+            .collect();
+        let bytes = file.len() + frames.iter().map(Vec::len).sum::<usize>();
+        let tag = frame::HEADER_LEN + 17;
+        file[tag..tag + 4].copy_from_slice(marker);
+        file[tag + 4..tag + 8].copy_from_slice(&0x7u32.to_be_bytes());
+        file[tag + 8..tag + 12].copy_from_slice(&(frames.len() as u32).to_be_bytes());
+        file[tag + 12..tag + 16].copy_from_slice(&(bytes as u32).to_be_bytes());
+        for i in 0..100 {
+            let offset = offsets[i * frames.len() / 100];
+            file[tag + 16 + i] = (offset * 256 / bytes) as u8;
+        }
+        file.extend(frames.concat());
+        file
+    }
+
+    /// Seeking to the start of any message gives the same messages as playing from the start.
+    fn assert_seeks_exactly(path: &str, message_count: usize) {
+        let mut harness = Harness::<AudioDataLoader>::open(&[path]).unwrap();
+        let audio = harness.info().channel("/audio").id;
+        let mut messages = |start_time| {
+            let args = MessageIteratorArgs {
+                start_time,
+                end_time: None,
+                channels: vec![audio],
+            };
+            let messages = harness.messages(args).unwrap();
+            messages
+                .into_iter()
+                .map(|msg| (msg.log_time, msg.data))
+                .collect::<Vec<_>>()
+        };
+        let all = messages(None);
+        assert_eq!(all.len(), message_count);
+        for (index, (log_time, _)) in all.iter().enumerate() {
+            assert_eq!(
+                messages(Some(*log_time)),
+                all[index..],
+                "seeking to {log_time}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_seek_constant_bitrate() {
+        // At 128 kbit/s frames average 417.96 bytes, so most of them are padded by a byte. The
+        // frame holding the tag is the first of the stream.
+        let end = |frame: u64| frame * 144 * 128_000 / 44_100;
+        let frames: Vec<Vec<u8>> = (1..=100)
+            .map(|i| silent_frame(9, end(i + 1) - end(i) == 418))
+            .collect();
+        reader::insert_file("cbr.mp3", tagged_file(b"Info", &frames));
+        conformance::assert_conforms::<AudioDataLoader>(&["cbr.mp3"]);
+        assert_seeks_exactly("cbr.mp3", 25);
+    }
+
+    #[test]
+    fn test_seek_bit_reservoir() {
+        // Every frame after the first takes some of its audio data from the frame before, so the
+        // first frame decoded after a seek can't be decoded
+        let frames: Vec<Vec<u8>> = (0..100)
+            .map(|i| {
+                let mut frame = silent_frame(9, false);
+                frame[frame::HEADER_LEN] = if i > 0 { 5 } else { 0 };
+                frame
+            })
+            .collect();
+        reader::insert_file("reservoir.mp3", frames.concat());
+        assert_seeks_exactly("reservoir.mp3", 25);
+    }
+
+    #[test]
+    fn test_seek_variable_bitrate() {
+        // Runs of 128 and 64 kbit/s frames, with a seek table that doesn't land on frame boundaries
+        let frames: Vec<Vec<u8>> = (0..100)
+            .map(|i| silent_frame(if i % 7 < 3 { 9 } else { 5 }, false))
+            .collect();
+        reader::insert_file("vbr.mp3", tagged_file(b"Xing", &frames));
+        conformance::assert_conforms::<AudioDataLoader>(&["vbr.mp3"]);
+        assert_seeks_exactly("vbr.mp3", 25);
+    }
+
+    #[test]
+    fn test_format_change() {
+        // Mono, then stereo audio. The chunk holding the change is split in two, so there are
+        // more messages than there are whole chunks in the file.
+        let frames: Vec<Vec<u8>> = (0..20)
+            .map(|i| {
+                let mut frame = silent_frame(9, false);
+                if i >= 10 {
+                    frame[3] = 0;
+                }
+                frame
+            })
+            .collect();
+        reader::insert_file("format-change.mp3", frames.concat());
+        conformance::assert_conforms::<AudioDataLoader>(&["format-change.mp3"]);
+
+        let mut harness = Harness::<AudioDataLoader>::open(&["format-change.mp3"]).unwrap();
+        let audio = harness.info().channel("/audio");
+        assert_eq!(audio.message_count, None);
+        let audio = audio.id;
+        let messages = harness.all_messages().unwrap();
+        assert_eq!(count_by_channel(&messages)[&audio], 6);
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        // Junk, then a frame cut off by the end of the file
+        let mut data = std::fs::read(SAMPLE).unwrap();
+        let first_frame = frame::id3v2_len(&data) as usize;
+        let frame = data[first_frame..first_frame + 200].to_vec();
+        data.extend([0x55; 100]);
+        data.extend(frame);
+        reader::insert_file("corrupt.mp3", data);
+
+        let mut harness = Harness::<AudioDataLoader>::open(&["corrupt.mp3"]).unwrap();
+        let messages: Vec<String> = harness
+            .info()
+            .problems
+            .iter()
+            .map(|problem| problem.message.clone())
+            .collect();
+        assert_eq!(messages, vec!["Skipped 300 bytes of invalid MP3 data"]);
+        assert_eq!(harness.info().end_time, START_TIME + 40 * FRAME_DURATION);
+        let audio = harness.info().channel("/audio").id;
+        let messages = harness.all_messages().unwrap();
+        assert_eq!(count_by_channel(&messages)[&audio], 10);
+    }
+
+    #[test]
+    fn test_truncated_id3_tag() {
+        // The tag's size runs past the end of the file
+        let mut data = std::fs::read(SAMPLE).unwrap();
+        let first_frame = frame::id3v2_len(&data) as usize;
+        data.splice(..first_frame, *b"ID3\x04\x00\x00\x7f\x7f\x7f\x7f");
+        reader::insert_file("truncated-tag.mp3", data);
+
+        let mut harness = Harness::<AudioDataLoader>::open(&["truncated-tag.mp3"]).unwrap();
+        let messages: Vec<String> = harness
+            .info()
+            .problems
+            .iter()
+            .map(|problem| problem.message.clone())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Failed reading ID3 tags: ID3v2 tag runs past the end of the file",
+                "Skipped 10 bytes of invalid MP3 data"
+            ]
+        );
+        // Without the tag, the file has no metadata and starts at 0
+        assert_eq!(harness.info().start_time, 0);
+        assert_eq!(harness.info().end_time, 40 * FRAME_DURATION);
+        let audio = harness.info().channel("/audio").id;
+        let messages = harness.all_messages().unwrap();
+        assert_eq!(count_by_channel(&messages)[&audio], 10);
+    }
+}
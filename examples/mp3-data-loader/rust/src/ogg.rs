@@ -0,0 +1,763 @@
+//! Reading of Ogg Vorbis files.
+//!
+//! An Ogg file is a sequence of pages, each holding the next segments of the packets of one
+//! logical stream. A file can interleave several streams, like a video and its audio, so the pages
+//! of the first Vorbis stream are picked out by their serial number. The stream starts with three
+//! header packets: the identification header with the sample format, the Vorbis comments and the
+//! codebooks needed to decode the audio packets that follow.
+//!
+//! Each page header has a granule position, the number of samples per channel decoded by the end
+//! of the last packet finishing on the page. Reading the page headers gives a time index of the
+//! file with an entry for every page. The audio packets are decoded with `lewton`.
+//!
+//! Ogg Opus files are recognized, but there's no Opus decoder that builds for WebAssembly without
+//! a C toolchain, so they're rejected as unsupported.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+};
+
+use lewton::{
+    audio::{PreviousWindowRight, read_audio_packet_generic},
+    header::{IdentHeader, SetupHeader, read_header_ident, read_header_setup},
+    samples::InterleavedSamples,
+};
+
+use crate::{AudioDecoder, FrameFormat, id3::Id3Tag};
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+/// The length of a page header before the segment table
+const HEADER_LEN: usize = 27;
+
+/// Page flag set when the first packet on the page continues from the previous page
+const CONTINUED: u8 = 0x01;
+/// Page flag set on the first page of a logical stream
+const FIRST_PAGE: u8 = 0x02;
+/// Page flag set on the last page of a logical stream
+const LAST_PAGE: u8 = 0x04;
+
+/// The granule position of a page where no packet finishes
+const NO_GRANULE: u64 = u64::MAX;
+
+/// The number of bytes read from the file at a time
+const READ_BUFFER_LEN: usize = 16 * 1024;
+
+const VORBIS_IDENT: &[u8] = b"\x01vorbis";
+const VORBIS_COMMENT: &[u8] = b"\x03vorbis";
+const VORBIS_SETUP: &[u8] = b"\x05vorbis";
+const OPUS_HEAD: &[u8] = b"OpusHead";
+
+/// A Vorbis stream in an Ogg file, indexed by page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OggInfo {
+    pub stream: VorbisStream,
+    /// The granule position and byte offset of pages the decoder can start after, see
+    /// [`OggDecoder::new`]
+    pub pages: Vec<(u64, u64)>,
+    /// The number of samples per channel in the stream
+    pub total_samples: u64,
+    /// Tags from the Vorbis comments
+    pub tag: Id3Tag,
+    /// The byte offset of the end of the stream's last page
+    pub audio_end: u64,
+    /// The number of bytes skipped because they weren't part of a valid page
+    pub skipped_bytes: u64,
+}
+
+/// What the decoder needs to know about a Vorbis stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VorbisStream {
+    pub serial: u32,
+    pub channels: u8,
+    pub sample_rate: u32,
+    /// The number of samples per channel in a long block. Each packet decodes to between a quarter
+    /// and half of this.
+    pub long_block_size: u32,
+    /// The identification and setup header packets
+    pub ident: Vec<u8>,
+    pub setup: Vec<u8>,
+    /// The byte offset of the first audio page
+    pub audio_start: u64,
+}
+
+impl OggInfo {
+    /// Read the headers of the first Vorbis stream in an Ogg file, then index its pages.
+    pub fn read(reader: impl Read) -> io::Result<Self> {
+        let mut pages = PageReader::new(reader);
+        let mut packets = PacketReader::default();
+        let mut headers = vec![];
+        // The first pages of every stream come before any other pages, and only hold the first
+        // header packet
+        let mut serial = None;
+        let mut opus = false;
+        let mut page = pages.next_page()?;
+        while let Some(first) = page.as_ref().filter(|page| page.flags & FIRST_PAGE != 0) {
+            if serial.is_none() && first.data.starts_with(VORBIS_IDENT) {
+                serial = Some(first.serial);
+                headers.extend(packets.push(first));
+            }
+            opus |= first.data.starts_with(OPUS_HEAD);
+            page = pages.next_page()?;
+        }
+        let Some(serial) = serial else {
+            return Err(match opus {
+                true => io::Error::new(io::ErrorKind::Unsupported, "Opus audio isn't supported"),
+                false => invalid_data("no Vorbis stream"),
+            });
+        };
+        while headers.len() < 3 {
+            let page = match page.take() {
+                Some(page) => page,
+                None => pages
+                    .next_page()?
+                    .ok_or_else(|| invalid_data("missing Vorbis headers"))?,
+            };
+            if page.serial == serial {
+                headers.extend(packets.push(&page));
+            }
+        }
+        // The audio starts on a new page after the setup header
+        let audio_start = pages.offset();
+        let (ident, comment, setup) = (&headers[0], &headers[1], &headers[2]);
+        if !comment.starts_with(VORBIS_COMMENT) || !setup.starts_with(VORBIS_SETUP) {
+            return Err(invalid_data("missing Vorbis headers"));
+        }
+        let stream = VorbisStream::parse(serial, ident, setup, audio_start)?;
+        let tag =
+            Id3Tag::parse_vorbis_comment(&comment[VORBIS_COMMENT.len()..]).unwrap_or_default();
+
+        let mut index = vec![];
+        let mut total_samples = 0;
+        let mut audio_end = stream.audio_start;
+        while let Some(page) = pages.next_page()? {
+            if page.serial != serial {
+                continue;
+            }
+            audio_end = pages.offset();
+            if let Some(granule) = page.granule() {
+                total_samples = granule;
+                if page.ends_whole_packet() {
+                    index.push((granule, page.offset));
+                }
+            }
+            // A chained file can start another stream with the same serial number
+            if page.flags & LAST_PAGE != 0 {
+                break;
+            }
+        }
+        Ok(Self {
+            stream,
+            pages: index,
+            total_samples,
+            tag,
+            audio_end,
+            skipped_bytes: pages.skipped_bytes(),
+        })
+    }
+}
+
+impl VorbisStream {
+    fn parse(serial: u32, ident: &[u8], setup: &[u8], audio_start: u64) -> io::Result<Self> {
+        // After the packet type and "vorbis" come a 32 bit version, the channel count, a 32 bit
+        // sample rate, three 32 bit bitrates and the two block sizes as powers of two
+        if ident.len() < 30 || !ident.starts_with(VORBIS_IDENT) {
+            return Err(invalid_data("invalid identification header"));
+        }
+        let channels = ident[11];
+        let sample_rate = u32::from_le_bytes(ident[12..16].try_into().unwrap());
+        // Block sizes are 64 to 8192 samples
+        let long_block_exp = ident[28] >> 4;
+        if channels == 0 || sample_rate == 0 || !(6..=13).contains(&long_block_exp) {
+            return Err(invalid_data("invalid identification header"));
+        }
+        Ok(Self {
+            serial,
+            channels,
+            sample_rate,
+            long_block_size: 1 << long_block_exp,
+            ident: ident.to_vec(),
+            setup: setup.to_vec(),
+            audio_start,
+        })
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid Ogg file: {msg}"),
+    )
+}
+
+/// A page of an Ogg file.
+#[derive(Debug, Clone, PartialEq)]
+struct Page {
+    /// The byte offset of the page from the start of the reader
+    offset: u64,
+    flags: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    /// The length of each segment of the page's data
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Page {
+    /// The number of samples decoded by the end of the last packet finishing on this page, if one
+    /// does.
+    fn granule(&self) -> Option<u64> {
+        (self.granule != NO_GRANULE).then_some(self.granule)
+    }
+
+    /// Whether the last packet finishing on this page also starts on it, so decoding can start on
+    /// this page and give exact audio from the next one.
+    fn ends_whole_packet(&self) -> bool {
+        let finished = self.segments.iter().filter(|&&len| len < 255).count();
+        finished >= 2 || (finished == 1 && self.flags & CONTINUED == 0)
+    }
+
+    /// The packet data on the page, with whether each packet finishes on the page. The last
+    /// packet continues on the next page if it doesn't.
+    fn packets(&self) -> Vec<(&[u8], bool)> {
+        let mut packets = vec![];
+        let (mut start, mut end) = (0, 0);
+        for &len in &self.segments {
+            end += len as usize;
+            if len < 255 {
+                packets.push((&self.data[start..end], true));
+                start = end;
+            }
+        }
+        if start < end {
+            packets.push((&self.data[start..end], false));
+        }
+        packets
+    }
+}
+
+/// Reads the pages of an Ogg file, skipping over anything that isn't a page with a valid CRC.
+struct PageReader<R> {
+    reader: R,
+    /// Bytes read but not consumed yet
+    buf: Vec<u8>,
+    /// The byte offset of the start of `buf`
+    offset: u64,
+    eof: bool,
+    skipped_bytes: u64,
+}
+
+impl<R: Read> PageReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            offset: 0,
+            eof: false,
+            skipped_bytes: 0,
+        }
+    }
+
+    /// The byte offset of the end of the last page read.
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// Read more data until at least `len` bytes are buffered, returning false if the end of the
+    /// file comes first.
+    fn fill(&mut self, len: usize) -> io::Result<bool> {
+        while self.buf.len() < len && !self.eof {
+            let filled = self.buf.len();
+            self.buf.resize(filled + READ_BUFFER_LEN, 0);
+            let read = self.reader.read(&mut self.buf[filled..])?;
+            self.buf.truncate(filled + read);
+            self.eof = read == 0;
+        }
+        Ok(self.buf.len() >= len)
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buf.drain(..len);
+        self.offset += len as u64;
+    }
+
+    fn next_page(&mut self) -> io::Result<Option<Page>> {
+        loop {
+            if !self.fill(HEADER_LEN)? {
+                self.skipped_bytes += self.buf.len() as u64;
+                self.consume(self.buf.len());
+                return Ok(None);
+            }
+            if let Some(page_len) = self.valid_page_len()? {
+                let buf = &self.buf;
+                let segment_count = buf[26] as usize;
+                let page = Page {
+                    offset: self.offset,
+                    flags: buf[5],
+                    granule: u64::from_le_bytes(buf[6..14].try_into().unwrap()),
+                    serial: u32::from_le_bytes(buf[14..18].try_into().unwrap()),
+                    sequence: u32::from_le_bytes(buf[18..22].try_into().unwrap()),
+                    segments: buf[HEADER_LEN..HEADER_LEN + segment_count].to_vec(),
+                    data: buf[HEADER_LEN + segment_count..page_len].to_vec(),
+                };
+                self.consume(page_len);
+                return Ok(Some(page));
+            }
+            // Resynchronize at the next possible capture pattern
+            let next = self.buf[1..]
+                .iter()
+                .position(|&byte| byte == CAPTURE_PATTERN[0])
+                .map_or(self.buf.len(), |pos| pos + 1);
+            self.skipped_bytes += next as u64;
+            self.consume(next);
+        }
+    }
+
+    /// The length of the page at the start of the buffer, if there's a whole page there with a
+    /// valid CRC.
+    fn valid_page_len(&mut self) -> io::Result<Option<usize>> {
+        if &self.buf[..4] != CAPTURE_PATTERN || self.buf[4] != 0 {
+            return Ok(None);
+        }
+        let data_start = HEADER_LEN + self.buf[26] as usize;
+        if !self.fill(data_start)? {
+            return Ok(None);
+        }
+        let data_len: usize = self.buf[HEADER_LEN..data_start]
+            .iter()
+            .map(|&len| len as usize)
+            .sum();
+        let page_len = data_start + data_len;
+        if !self.fill(page_len)? {
+            return Ok(None);
+        }
+        // The CRC is calculated with the CRC field set to 0
+        let page = &self.buf[..page_len];
+        let crc = crc32(crc32(crc32(0, &page[..22]), &[0; 4]), &page[26..]);
+        Ok((crc.to_le_bytes() == page[22..26]).then_some(page_len))
+    }
+}
+
+/// Joins the segments of consecutive pages of a stream into packets.
+#[derive(Default)]
+struct PacketReader {
+    /// The start of a packet continuing on the next page
+    partial: Vec<u8>,
+    /// The sequence number expected for the next page
+    next_sequence: Option<u32>,
+}
+
+impl PacketReader {
+    /// Add the next page of the stream, returning the packets finishing on it. The start of a
+    /// packet continuing from a page that wasn't read, like the page before the first one or a
+    /// missing page, is dropped.
+    fn push(&mut self, page: &Page) -> Vec<Vec<u8>> {
+        if self.next_sequence != Some(page.sequence) || page.flags & CONTINUED == 0 {
+            self.partial.clear();
+        }
+        self.next_sequence = Some(page.sequence.wrapping_add(1));
+        let mut packets = vec![];
+        for (i, (data, finished)) in page.packets().into_iter().enumerate() {
+            let continued = i == 0 && page.flags & CONTINUED != 0;
+            if continued && self.partial.is_empty() {
+                continue;
+            }
+            self.partial.extend_from_slice(data);
+            if finished {
+                packets.push(std::mem::take(&mut self.partial));
+            }
+        }
+        packets
+    }
+}
+
+/// The CRC-32 of an Ogg page, with polynomial `0x04c11db7` and no reflection.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Decodes the audio packets of a Vorbis stream, one packet at a time.
+pub struct OggDecoder<R: Read> {
+    pages: PageReader<R>,
+    packets: PacketReader,
+    serial: u32,
+    ident: IdentHeader,
+    setup: SetupHeader,
+    /// The end of the previous packet, which overlaps the start of the next one
+    window: PreviousWindowRight,
+    /// Whether the packets on the next page only prime the decoder, see [`OggDecoder::new`]
+    priming: bool,
+    /// The granule position of the last page decoded, None before the first page of the stream
+    granule: Option<u64>,
+    /// Decoded packets not returned yet, as interleaved samples
+    decoded: VecDeque<Vec<f32>>,
+    done: bool,
+    undecodable_bytes: u64,
+}
+
+impl<R: Read> OggDecoder<R> {
+    /// Create a decoder reading from `reader`, which should be positioned at the start of a page.
+    ///
+    /// Each Vorbis packet overlaps the previous one, so the first packet decoded gives no audio.
+    /// At the start of the stream that's expected, but anywhere else the decoder is started a page
+    /// early: the packets on that page are decoded and thrown away, so the audio starts exactly at
+    /// the page's granule position. `priming` starts the decoder this way, on a page from
+    /// [`OggInfo::pages`].
+    pub fn new(reader: R, stream: &VorbisStream, priming: bool) -> anyhow::Result<Self> {
+        let ident = read_header_ident(&stream.ident)
+            .map_err(|err| anyhow::anyhow!("invalid Vorbis identification header: {err:?}"))?;
+        let setup = read_header_setup(
+            &stream.setup,
+            ident.audio_channels,
+            (ident.blocksize_0, ident.blocksize_1),
+        )
+        .map_err(|err| anyhow::anyhow!("invalid Vorbis setup header: {err:?}"))?;
+        Ok(Self {
+            pages: PageReader::new(reader),
+            packets: PacketReader::default(),
+            serial: stream.serial,
+            ident,
+            setup,
+            window: PreviousWindowRight::new(),
+            priming,
+            granule: None,
+            decoded: VecDeque::new(),
+            done: false,
+            undecodable_bytes: 0,
+        })
+    }
+
+    /// Decode the packets finishing on the next page of the stream, returning false at the end of
+    /// the stream.
+    fn decode_page(&mut self) -> anyhow::Result<bool> {
+        let page = loop {
+            match self.pages.next_page()? {
+                Some(page) if page.serial == self.serial => break page,
+                Some(_) => (),
+                None => return Ok(false),
+            }
+        };
+        self.done = page.flags & LAST_PAGE != 0;
+        let mut decoded = vec![];
+        for packet in self.packets.push(&page) {
+            match read_audio_packet_generic::<InterleavedSamples<f32>>(
+                &self.ident,
+                &self.setup,
+                &packet,
+                &mut self.window,
+            ) {
+                Ok(samples) => decoded.push(samples.samples),
+                // Start over from the next packet, as if after a seek
+                Err(_) => {
+                    self.undecodable_bytes += packet.len() as u64;
+                    self.window = PreviousWindowRight::new();
+                }
+            }
+        }
+        if self.priming {
+            self.priming = false;
+            self.granule = page.granule();
+            return Ok(true);
+        }
+        if let Some(granule) = page.granule() {
+            // The granule position marks where the audio really starts and ends: the first page
+            // can have fewer samples than were decoded, to be dropped from the start, and the
+            // last page can have fewer samples, to be dropped from the end.
+            let channels = self.ident.audio_channels as usize;
+            let decoded_len: usize = decoded.iter().map(Vec::len).sum();
+            match self.granule {
+                None => {
+                    let excess = decoded_len.saturating_sub(granule as usize * channels);
+                    trim(&mut decoded, excess, true);
+                }
+                Some(start) if self.done => {
+                    let len = granule.saturating_sub(start) as usize * channels;
+                    trim(&mut decoded, decoded_len.saturating_sub(len), false);
+                }
+                Some(_) => (),
+            }
+            self.granule = Some(granule);
+        }
+        self.decoded
+            .extend(decoded.into_iter().filter(|samples| !samples.is_empty()));
+        Ok(true)
+    }
+}
+
+/// Remove `len` samples from the start or end of a sequence of packets.
+fn trim(packets: &mut Vec<Vec<f32>>, mut len: usize, from_start: bool) {
+    if from_start {
+        packets.reverse();
+    }
+    while len > 0 {
+        let Some(packet) = packets.last_mut() else {
+            break;
+        };
+        let removed = len.min(packet.len());
+        if from_start {
+            packet.drain(..removed);
+        } else {
+            packet.truncate(packet.len() - removed);
+        }
+        len -= removed;
+        if packet.is_empty() {
+            packets.pop();
+        }
+    }
+    if from_start {
+        packets.reverse();
+    }
+}
+
+impl<R: Read> AudioDecoder for OggDecoder<R> {
+    fn decode(&mut self, samples: &mut Vec<f32>) -> anyhow::Result<Option<FrameFormat>> {
+        loop {
+            if let Some(packet) = self.decoded.pop_front() {
+                *samples = packet;
+                return Ok(Some(FrameFormat {
+                    channels: self.ident.audio_channels as usize,
+                    sample_rate: self.ident.audio_sample_rate,
+                }));
+            }
+            if self.done || !self.decode_page()? {
+                self.done = true;
+                return Ok(None);
+            }
+        }
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.pages.skipped_bytes() + self.undecodable_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SERIAL: u32 = 0x1234;
+
+    /// Build a page from its packets.
+    fn page(flags: u8, granule: u64, serial: u32, sequence: u32, packets: &[&[u8]]) -> Vec<u8> {
+        page_with(flags, granule, serial, sequence, packets, true)
+    }
+
+    /// Build a page from its packets, leaving the last one unfinished if `finished` is false. An
+    /// unfinished packet has to be a multiple of 255 bytes long.
+    fn page_with(
+        flags: u8,
+        granule: u64,
+        serial: u32,
+        sequence: u32,
+        packets: &[&[u8]],
+        finished: bool,
+    ) -> Vec<u8> {
+        let mut segments = vec![];
+        for (i, packet) in packets.iter().enumerate() {
+            segments.extend(std::iter::repeat_n(255, packet.len() / 255));
+            if finished || i < packets.len() - 1 {
+                segments.push((packet.len() % 255) as u8);
+            }
+        }
+        let mut page = CAPTURE_PATTERN.to_vec();
+        page.extend([0, flags]);
+        page.extend(granule.to_le_bytes());
+        page.extend(serial.to_le_bytes());
+        page.extend(sequence.to_le_bytes());
+        page.extend([0; 4]);
+        page.push(segments.len() as u8);
+        page.extend(segments);
+        for packet in packets {
+            page.extend(*packet);
+        }
+        let crc = crc32(0, &page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// A stereo, 44.1 kHz identification header with 256 and 2048 sample blocks.
+    fn ident_header() -> Vec<u8> {
+        let mut ident = VORBIS_IDENT.to_vec();
+        ident.extend(0u32.to_le_bytes());
+        ident.push(2);
+        ident.extend(44_100u32.to_le_bytes());
+        ident.extend([0; 12]);
+        ident.extend([0xb8, 0x01]);
+        ident
+    }
+
+    fn comment_header() -> Vec<u8> {
+        let mut comment = VORBIS_COMMENT.to_vec();
+        comment.extend(4u32.to_le_bytes());
+        comment.extend(b"test");
+        comment.extend(1u32.to_le_bytes());
+        let field = b"TITLE=Engine run 3";
+        comment.extend((field.len() as u32).to_le_bytes());
+        comment.extend(field);
+        comment.push(1);
+        comment
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc32(0, b"123456789"), 0x89a1_897f);
+    }
+
+    #[test]
+    fn test_read_pages() {
+        let mut file = b"junk".to_vec();
+        let first = page(FIRST_PAGE, 0, SERIAL, 0, &[b"abc"]);
+        file.extend(&first);
+        let mut corrupt = page(0, 10, SERIAL, 1, &[b"def"]);
+        corrupt[30] ^= 1;
+        file.extend(&corrupt);
+        let last = page(LAST_PAGE, 20, SERIAL, 2, &[b"ghi", &[7; 300]]);
+        file.extend(&last);
+
+        let mut pages = PageReader::new(Cursor::new(&file));
+        let page = pages.next_page().unwrap().unwrap();
+        assert_eq!(
+            (page.offset, page.flags, page.granule()),
+            (4, FIRST_PAGE, Some(0))
+        );
+        assert_eq!(page.packets(), vec![(&b"abc"[..], true)]);
+        let page = pages.next_page().unwrap().unwrap();
+        assert_eq!(page.offset, 4 + (first.len() + corrupt.len()) as u64);
+        assert_eq!((page.sequence, page.granule()), (2, Some(20)));
+        assert_eq!(page.packets().len(), 2);
+        assert!(pages.next_page().unwrap().is_none());
+        assert_eq!(pages.skipped_bytes(), 4 + corrupt.len() as u64);
+    }
+
+    #[test]
+    fn test_join_packets() {
+        let long: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let pages = [
+            page_with(0, 0, SERIAL, 5, &[b"a", &long[..255]], false),
+            page_with(CONTINUED, NO_GRANULE, SERIAL, 6, &[&long[255..510]], false),
+            page(CONTINUED, 100, SERIAL, 7, &[&long[510..], b"b"]),
+        ]
+        .map(|page| {
+            PageReader::new(Cursor::new(page))
+                .next_page()
+                .unwrap()
+                .unwrap()
+        });
+        assert!(pages[0].ends_whole_packet());
+        assert!(!pages[1].ends_whole_packet());
+        assert!(pages[2].ends_whole_packet());
+
+        let mut packets = PacketReader::default();
+        assert_eq!(packets.push(&pages[0]), vec![b"a".to_vec()]);
+        assert!(packets.push(&pages[1]).is_empty());
+        assert_eq!(packets.push(&pages[2]), vec![long.clone(), b"b".to_vec()]);
+
+        // Starting partway through a packet, or after a missing page, drops the rest of it
+        let mut packets = PacketReader::default();
+        assert!(packets.push(&pages[1]).is_empty());
+        assert_eq!(packets.push(&pages[2]), vec![b"b".to_vec()]);
+        let mut packets = PacketReader::default();
+        packets.push(&pages[0]);
+        assert_eq!(packets.push(&pages[2]), vec![b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut packets = vec![vec![1.0; 4], vec![2.0; 4]];
+        trim(&mut packets, 6, true);
+        assert_eq!(packets, vec![vec![2.0; 2]]);
+        let mut packets = vec![vec![1.0; 4], vec![2.0; 4]];
+        trim(&mut packets, 5, false);
+        assert_eq!(packets, vec![vec![1.0; 3]]);
+    }
+
+    #[test]
+    fn test_read_info() {
+        let other = 0x99;
+        let mut file = vec![];
+        file.extend(page(FIRST_PAGE, 0, SERIAL, 0, &[&ident_header()]));
+        file.extend(page(FIRST_PAGE, 0, other, 0, &[b"\x80theora"]));
+        file.extend(page(0, 0, SERIAL, 1, &[&comment_header(), b"\x05vorbis"]));
+        let audio_start = file.len() as u64;
+        let mut offsets = vec![];
+        for (sequence, granule) in [(2, 1024), (3, 2048), (4, 4096)] {
+            offsets.push(file.len() as u64);
+            file.extend(page(0, granule, SERIAL, sequence, &[b"audio", b"audio"]));
+            file.extend(page(0, 0, other, sequence, &[b"video"]));
+        }
+        offsets.push(file.len() as u64);
+        // A packet continuing onto a page where nothing else finishes
+        file.extend(page_with(0, 5000, SERIAL, 5, &[b"audio", &[0; 255]], false));
+        file.extend(page(CONTINUED, 6000, SERIAL, 6, &[b"end"]));
+        file.extend(page(LAST_PAGE, 7000, SERIAL, 7, &[b"end"]));
+        let audio_end = file.len() as u64;
+        // A chained stream reusing the serial number
+        file.extend(page(FIRST_PAGE, 0, SERIAL, 0, &[&ident_header()]));
+
+        let info = OggInfo::read(Cursor::new(&file)).unwrap();
+        assert_eq!(info.stream.serial, SERIAL);
+        assert_eq!(info.stream.channels, 2);
+        assert_eq!(info.stream.sample_rate, 44_100);
+        assert_eq!(info.stream.long_block_size, 2048);
+        assert_eq!(info.stream.ident, ident_header());
+        assert_eq!(info.stream.audio_start, audio_start);
+        assert_eq!(info.tag.title.as_deref(), Some("Engine run 3"));
+        assert_eq!(
+            info.pages,
+            vec![
+                (1024, offsets[0]),
+                (2048, offsets[1]),
+                (4096, offsets[2]),
+                (5000, offsets[3]),
+                (7000, audio_end - 31)
+            ]
+        );
+        assert_eq!(info.total_samples, 7000);
+        assert_eq!(info.audio_end, audio_end);
+        assert_eq!(info.skipped_bytes, 0);
+    }
+
+    #[test]
+    fn test_read_opus() {
+        let mut head = OPUS_HEAD.to_vec();
+        head.extend([1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        let file = page(FIRST_PAGE, 0, SERIAL, 0, &[&head]);
+        let err = OggInfo::read(Cursor::new(&file)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let err = OggInfo::read(Cursor::new(b"fLaC")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
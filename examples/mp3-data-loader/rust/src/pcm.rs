@@ -0,0 +1,183 @@
+//! Conversion of decoded samples into the PCM formats supported by `foxglove.RawAudio`.
+
+/// The sample format of published audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    /// Signed 16 bit little-endian integers
+    S16,
+    /// 32 bit little-endian floats in the range [-1, 1]
+    #[allow(dead_code)] // Selected by changing `OUTPUT_FORMAT`
+    F32,
+}
+
+impl PcmFormat {
+    /// The name of the format used in the `format` field of `foxglove.RawAudio`.
+    pub fn name(self) -> &'static str {
+        match self {
+            PcmFormat::S16 => "pcm-s16",
+            PcmFormat::F32 => "pcm-f32",
+        }
+    }
+}
+
+/// Encodes decoded floating point samples into PCM bytes.
+pub struct PcmEncoder {
+    format: PcmFormat,
+    dither: Option<Dither>,
+}
+
+impl PcmEncoder {
+    /// Create an encoder for `format`. When `dither` is set, triangular dither noise is added to
+    /// samples before they are quantized to 16 bits.
+    pub fn new(format: PcmFormat, dither: bool) -> Self {
+        Self {
+            format,
+            dither: (dither && format == PcmFormat::S16).then(Dither::default),
+        }
+    }
+
+    pub fn format(&self) -> PcmFormat {
+        self.format
+    }
+
+    /// Append `samples` to `out` in the encoder's format.
+    ///
+    /// Decoders can produce samples slightly outside of [-1, 1], these are clipped to full scale.
+    pub fn encode(&mut self, samples: &[f32], out: &mut Vec<u8>) {
+        match self.format {
+            PcmFormat::S16 => {
+                out.reserve(samples.len() * 2);
+                for &sample in samples {
+                    let noise = self.dither.as_mut().map_or(0.0, Dither::next);
+                    out.extend(f32_to_s16(sample, noise).to_le_bytes());
+                }
+            }
+            PcmFormat::F32 => {
+                out.reserve(samples.len() * 4);
+                for &sample in samples {
+                    out.extend(clip(sample).to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Clip a sample to [-1, 1], mapping NaN to silence.
+fn clip(sample: f32) -> f32 {
+    if sample.is_nan() {
+        return 0.0;
+    }
+    sample.clamp(-1.0, 1.0)
+}
+
+/// Quantize a sample to 16 bits, adding `noise` (in units of the least significant bit) first.
+fn f32_to_s16(sample: f32, noise: f32) -> i16 {
+    let scaled = clip(sample) * -(i16::MIN as f32) + noise;
+    scaled.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Generates triangular (TPDF) dither noise with an amplitude of ±1 LSB.
+///
+/// This uses a small xorshift generator, which is plenty for decorrelating quantization error.
+struct Dither {
+    state: u32,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self { state: 0x9e37_79b9 }
+    }
+}
+
+impl Dither {
+    fn next(&mut self) -> f32 {
+        self.uniform() - self.uniform()
+    }
+
+    /// A uniformly distributed value in [0, 1).
+    fn uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second of a 440 Hz sine wave at 44.1 kHz, with the given peak amplitude.
+    fn sine(amplitude: f32) -> Vec<f32> {
+        (0..44_100)
+            .map(|i| amplitude * (i as f32 * 440.0 * std::f32::consts::TAU / 44_100.0).sin())
+            .collect()
+    }
+
+    fn decode_s16(data: &[u8]) -> Vec<f32> {
+        data.chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / -(i16::MIN as f32))
+            .collect()
+    }
+
+    fn decode_f32(data: &[u8]) -> Vec<f32> {
+        data.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    fn encode(format: PcmFormat, dither: bool, samples: &[f32]) -> Vec<u8> {
+        let mut data = vec![];
+        PcmEncoder::new(format, dither).encode(samples, &mut data);
+        data
+    }
+
+    #[test]
+    fn test_s16_round_trip() {
+        let samples = sine(0.8);
+        let decoded = decode_s16(&encode(PcmFormat::S16, false, &samples));
+        assert_eq!(decoded.len(), samples.len());
+        for (a, b) in samples.iter().zip(&decoded) {
+            assert!((a - b).abs() <= 0.5 / 32768.0, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn test_s16_dither_round_trip() {
+        let samples = sine(0.8);
+        let decoded = decode_s16(&encode(PcmFormat::S16, true, &samples));
+        for (a, b) in samples.iter().zip(&decoded) {
+            assert!((a - b).abs() <= 1.5 / 32768.0, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn test_f32_round_trip() {
+        let samples = sine(0.8);
+        assert_eq!(
+            decode_f32(&encode(PcmFormat::F32, false, &samples)),
+            samples
+        );
+    }
+
+    #[test]
+    fn test_over_range_is_clipped() {
+        // A sine wave that goes well past full scale should be flattened at the peaks rather than
+        // wrapping around.
+        let samples = sine(1.5);
+        let decoded = decode_s16(&encode(PcmFormat::S16, false, &samples));
+        for (a, b) in samples.iter().zip(&decoded) {
+            assert!(
+                (a.clamp(-1.0, 1.0) - b).abs() <= 1.0 / 32768.0,
+                "{a} != {b}"
+            );
+        }
+        assert_eq!(f32_to_s16(1.5, 0.0), i16::MAX);
+        assert_eq!(f32_to_s16(-1.5, 0.0), i16::MIN);
+        assert_eq!(f32_to_s16(1.0, 1.0), i16::MAX);
+        assert_eq!(f32_to_s16(f32::NAN, 0.0), 0);
+
+        let decoded = decode_f32(&encode(PcmFormat::F32, false, &samples));
+        assert!(decoded.iter().all(|s| (-1.0..=1.0).contains(s)));
+    }
+}
@@ -0,0 +1,211 @@
+//! Sample rate conversion of decoded audio.
+//!
+//! Samples are interpolated with a windowed sinc filter. When reducing the sample rate the
+//! filter's cutoff is lowered to the new Nyquist frequency, so frequencies that can't be
+//! represented are removed instead of aliasing.
+
+use std::f64::consts::PI;
+
+/// The number of input samples on each side of an output sample used to interpolate it, before
+/// widening the filter for downsampling.
+const HALF_TAPS: usize = 16;
+
+/// The number of entries in the filter table per input sample.
+const TABLE_RESOLUTION: usize = 512;
+
+/// Converts a stream of interleaved samples to a fixed sample rate.
+pub struct Resampler {
+    output_rate: u32,
+    input_rate: u32,
+    channels: usize,
+    /// The half width of the filter in input samples
+    half_width: usize,
+    /// The filter sampled at `TABLE_RESOLUTION` points per input sample, from the center out
+    table: Vec<f32>,
+    /// Interleaved input samples still needed to interpolate upcoming output samples
+    history: Vec<f32>,
+    /// The position of the next output sample in input samples from the start of `history`
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(output_rate: u32) -> Self {
+        Self {
+            output_rate,
+            input_rate: 0,
+            channels: 0,
+            half_width: 0,
+            table: Vec::new(),
+            history: Vec::new(),
+            position: 0.0,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// Resample a block of interleaved samples, returning the samples at the output rate.
+    ///
+    /// Each output sample needs some input samples after it, so output lags behind the input by
+    /// a few samples. If the input sample rate or channel count changes, samples held back from
+    /// the previous block are dropped.
+    pub fn process(&mut self, input: &[f32], channels: usize, input_rate: u32) -> Vec<f32> {
+        if input_rate == self.output_rate {
+            self.input_rate = input_rate;
+            self.history.clear();
+            return input.to_vec();
+        }
+        if input_rate != self.input_rate || channels != self.channels {
+            self.reset(channels, input_rate);
+        }
+        self.history.extend_from_slice(input);
+
+        let step = input_rate as f64 / self.output_rate as f64;
+        let frames = self.history.len() / channels;
+        let mut output = Vec::with_capacity((input.len() as f64 / step) as usize + channels);
+        while (self.position.floor() as usize) + self.half_width < frames {
+            let center = self.position.floor() as usize;
+            let first = center + 1 - self.half_width;
+            let start = output.len();
+            output.resize(start + channels, 0.0);
+            for i in first..=center + self.half_width {
+                let distance = (i as f64 - self.position).abs();
+                let weight = self.table[(distance * TABLE_RESOLUTION as f64).round() as usize];
+                let frame = &self.history[i * channels..(i + 1) * channels];
+                for (out, &sample) in output[start..].iter_mut().zip(frame) {
+                    *out += sample * weight;
+                }
+            }
+            self.position += step;
+        }
+
+        // Drop the input samples no longer needed by the filter
+        let keep_from = (self.position.floor() as usize + 1).saturating_sub(self.half_width);
+        let keep_from = keep_from.min(frames);
+        self.history.drain(..keep_from * channels);
+        self.position -= keep_from as f64;
+        output
+    }
+
+    fn reset(&mut self, channels: usize, input_rate: u32) {
+        self.input_rate = input_rate;
+        self.channels = channels;
+        // Lower the cutoff to the output Nyquist frequency when downsampling
+        let cutoff = (self.output_rate as f64 / input_rate as f64).min(1.0);
+        self.half_width = (HALF_TAPS as f64 / cutoff).ceil() as usize;
+        let half_width = self.half_width as f64;
+        self.table = (0..=self.half_width * TABLE_RESOLUTION + TABLE_RESOLUTION)
+            .map(|i| {
+                let x = i as f64 / TABLE_RESOLUTION as f64;
+                if x >= half_width {
+                    return 0.0;
+                }
+                // A Blackman window over the width of the filter
+                let t = 0.5 + 0.5 * x / half_width;
+                let window = 0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos();
+                (cutoff * sinc(cutoff * x) * window) as f32
+            })
+            .collect();
+        // Start with silence before the first sample, so the first output sample lines up with
+        // the first input sample
+        self.history = vec![0.0; (self.half_width - 1) * channels];
+        self.position = (self.half_width - 1) as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: u32, range: std::ops::Range<usize>) -> Vec<f32> {
+        range
+            .map(|i| (0.5 * (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    /// Resample in blocks the size of an MPEG frame.
+    fn resample(resampler: &mut Resampler, input: &[f32], channels: usize, rate: u32) -> Vec<f32> {
+        input
+            .chunks(1152 * channels)
+            .flat_map(|block| resampler.process(block, channels, rate))
+            .collect()
+    }
+
+    #[test]
+    fn test_upsample_sine() {
+        let mut resampler = Resampler::new(48_000);
+        let output = resample(&mut resampler, &sine(1000.0, 44_100, 0..44_100), 1, 44_100);
+        // Only the samples at the end needing input that hasn't arrived are held back
+        assert!(
+            (47_970..=48_000).contains(&output.len()),
+            "{}",
+            output.len()
+        );
+        let expected = sine(1000.0, 48_000, 0..output.len());
+        // Skip the start, where the filter runs into the silence before the first sample
+        for (i, (out, expected)) in output.iter().zip(&expected).enumerate().skip(100) {
+            assert!((out - expected).abs() < 1e-3, "{i}: {out} {expected}");
+        }
+    }
+
+    #[test]
+    fn test_downsample_removes_high_frequencies() {
+        let mut resampler = Resampler::new(22_050);
+        // 15 kHz can't be represented at 22.05 kHz
+        let output = resample(
+            &mut resampler,
+            &sine(15_000.0, 44_100, 0..44_100),
+            1,
+            44_100,
+        );
+        assert!(
+            (22_000..=22_050).contains(&output.len()),
+            "{}",
+            output.len()
+        );
+        let peak = output[100..].iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak < 0.01, "{peak}");
+
+        // While lower frequencies pass through
+        let mut resampler = Resampler::new(22_050);
+        let output = resample(&mut resampler, &sine(1000.0, 44_100, 0..44_100), 1, 44_100);
+        let expected = sine(1000.0, 22_050, 0..output.len());
+        for (i, (out, expected)) in output.iter().zip(&expected).enumerate().skip(100) {
+            assert!((out - expected).abs() < 2e-3, "{i}: {out} {expected}");
+        }
+    }
+
+    #[test]
+    fn test_stereo() {
+        let left = sine(1000.0, 32_000, 0..3200);
+        let input: Vec<f32> = left.iter().flat_map(|&s| [s, -s]).collect();
+        let mut resampler = Resampler::new(48_000);
+        let output = resample(&mut resampler, &input, 2, 32_000);
+        assert_eq!(output.len() % 2, 0);
+        for frame in output.chunks(2) {
+            assert_eq!(frame[0], -frame[1]);
+        }
+    }
+
+    #[test]
+    fn test_passthrough_and_rate_change() {
+        let mut resampler = Resampler::new(48_000);
+        let input = sine(1000.0, 48_000, 0..1152);
+        assert_eq!(resampler.process(&input, 1, 48_000), input);
+        // A frame at a different rate is converted, the first output sample matching the first
+        // input sample
+        let output = resampler.process(&sine(1000.0, 24_000, 0..1152), 1, 24_000);
+        assert!(output[0].abs() < 1e-6);
+        assert!((2270..=2304).contains(&output.len()), "{}", output.len());
+        assert_eq!(resampler.process(&input, 1, 48_000), input);
+    }
+}
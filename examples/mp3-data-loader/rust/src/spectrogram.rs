@@ -0,0 +1,301 @@
+//! A scrolling spectrogram of the audio, rendered as grayscale images.
+//!
+//! The audio is mixed down to mono and split into consecutive windows. Each window is transformed
+//! with an FFT and becomes one column of the image, with the lowest frequency at the bottom. Every
+//! few columns an image of the most recent columns is produced, so the spectrogram scrolls from
+//! right to left during playback.
+
+use std::{collections::VecDeque, f32::consts::PI};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpectrogramConfig {
+    /// The number of samples transformed for each column. This must be a power of two.
+    pub window: usize,
+    /// The highest frequency shown in Hz, limited to half the sample rate
+    pub max_frequency: f32,
+    /// The number of columns in each image
+    pub columns: usize,
+    /// The number of new columns between images
+    pub step: usize,
+    /// The levels in dBFS shown as black and white
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+/// A grayscale image with one byte per pixel, in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct Spectrogram {
+    config: SpectrogramConfig,
+    sample_rate: u32,
+    fft: Fft,
+    /// The Hann window applied to the samples before the FFT
+    window: Vec<f32>,
+    /// Scales FFT magnitudes so a full scale sine is 0 dBFS
+    scale: f32,
+    /// Mono samples that haven't filled a window yet
+    samples: Vec<f32>,
+    /// The most recent columns, oldest first, each with the lowest frequency first
+    columns: VecDeque<Vec<u8>>,
+    /// The number of columns added since the last image
+    new_columns: usize,
+}
+
+impl Spectrogram {
+    pub fn new(config: SpectrogramConfig) -> Self {
+        assert!(
+            config.window.is_power_of_two(),
+            "spectrogram window must be a power of two"
+        );
+        let window: Vec<f32> = (0..config.window)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / config.window as f32).cos())
+            .collect();
+        Self {
+            config,
+            sample_rate: 0,
+            fft: Fft::new(config.window),
+            scale: 2.0 / window.iter().sum::<f32>(),
+            window,
+            samples: Vec::with_capacity(config.window),
+            columns: VecDeque::with_capacity(config.columns),
+            new_columns: 0,
+        }
+    }
+
+    /// Add a block of interleaved samples, returning an image if enough new columns have been
+    /// computed since the last one.
+    ///
+    /// The spectrogram is cleared if the sample rate changes.
+    pub fn push(&mut self, samples: &[f32], channels: usize, sample_rate: u32) -> Option<Image> {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.samples.clear();
+            self.columns.clear();
+            self.new_columns = 0;
+        }
+        self.samples.extend(
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        let mut start = 0;
+        while self.samples.len() - start >= self.config.window {
+            let column = self.column(start);
+            if self.columns.len() == self.config.columns {
+                self.columns.pop_front();
+            }
+            self.columns.push_back(column);
+            self.new_columns += 1;
+            start += self.config.window;
+        }
+        self.samples.drain(..start);
+
+        if self.new_columns < self.config.step {
+            return None;
+        }
+        // Columns past the step count towards the next image, so there's an image every `step`
+        // columns however the samples are split into pushes
+        self.new_columns -= self.config.step;
+        Some(self.image())
+    }
+
+    /// The number of frequency bins shown, which is the height of the image.
+    fn bins(&self) -> usize {
+        let resolution = self.sample_rate as f32 / self.config.window as f32;
+        let bins = (self.config.max_frequency / resolution) as usize + 1;
+        bins.min(self.config.window / 2 + 1)
+    }
+
+    /// Transform the window of samples starting at `start` into a column of pixels.
+    fn column(&mut self, start: usize) -> Vec<u8> {
+        let samples = &self.samples[start..start + self.config.window];
+        let mut re: Vec<f32> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, window)| sample * window)
+            .collect();
+        let mut im = vec![0.0; self.config.window];
+        self.fft.transform(&mut re, &mut im);
+
+        let SpectrogramConfig { min_db, max_db, .. } = self.config;
+        (0..self.bins())
+            .map(|bin| {
+                let magnitude = (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() * self.scale;
+                let db = 20.0 * magnitude.max(1e-10).log10();
+                ((db - min_db) / (max_db - min_db)).clamp(0.0, 1.0) * 255.0
+            })
+            .map(|level| level.round() as u8)
+            .collect()
+    }
+
+    /// Render the current columns, with the newest on the right. Columns that haven't been
+    /// computed yet are black.
+    fn image(&self) -> Image {
+        let width = self.config.columns;
+        let height = self.bins();
+        let mut data = vec![0; width * height];
+        let offset = width - self.columns.len();
+        for (x, column) in self.columns.iter().enumerate() {
+            for (bin, &level) in column.iter().enumerate() {
+                data[(height - 1 - bin) * width + offset + x] = level;
+            }
+        }
+        Image {
+            width: width as u32,
+            height: height as u32,
+            data,
+        }
+    }
+}
+
+/// An in-place radix-2 FFT of a fixed power of two length.
+struct Fft {
+    /// `e^(-2πik/n)` for `k` in `0..n/2`
+    twiddles: Vec<(f32, f32)>,
+}
+
+impl Fft {
+    fn new(len: usize) -> Self {
+        let twiddles = (0..len / 2)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f32 / len as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        Self { twiddles }
+    }
+
+    fn transform(&self, re: &mut [f32], im: &mut [f32]) {
+        let n = re.len();
+        // Reorder the input into bit-reversed order
+        let bits = n.trailing_zeros();
+        for i in 0..n {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+        let mut len = 2;
+        while len <= n {
+            let stride = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..len / 2 {
+                    let (w_re, w_im) = self.twiddles[k * stride];
+                    let (a, b) = (start + k, start + k + len / 2);
+                    let t_re = re[b] * w_re - im[b] * w_im;
+                    let t_im = re[b] * w_im + im[b] * w_re;
+                    re[b] = re[a] - t_re;
+                    im[b] = im[a] - t_im;
+                    re[a] += t_re;
+                    im[a] += t_im;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: SpectrogramConfig = SpectrogramConfig {
+        window: 256,
+        max_frequency: 2000.0,
+        columns: 8,
+        step: 4,
+        min_db: -100.0,
+        max_db: 0.0,
+    };
+
+    fn sine(frequency: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_fft_matches_dft() {
+        let input: Vec<f32> = (0..16).map(|i| ((i * 7) % 5) as f32 - 2.0).collect();
+        let mut re = input.clone();
+        let mut im = vec![0.0; 16];
+        Fft::new(16).transform(&mut re, &mut im);
+        for k in 0..16 {
+            let (mut dft_re, mut dft_im) = (0.0, 0.0);
+            for (i, x) in input.iter().enumerate() {
+                let angle = -2.0 * PI * (k * i) as f32 / 16.0;
+                dft_re += x * angle.cos();
+                dft_im += x * angle.sin();
+            }
+            assert!((re[k] - dft_re).abs() < 1e-3, "{k}: {} {dft_re}", re[k]);
+            assert!((im[k] - dft_im).abs() < 1e-3, "{k}: {} {dft_im}", im[k]);
+        }
+    }
+
+    #[test]
+    fn test_spectrogram() {
+        // At 8 kHz with a 256 sample window each bin is 31.25 Hz, so 1 kHz is bin 32
+        let mut spectrogram = Spectrogram::new(CONFIG);
+        let samples = sine(1000.0, 8000, 256 * 4);
+        assert_eq!(spectrogram.push(&samples[..256 * 3], 1, 8000), None);
+        let image = spectrogram.push(&samples[256 * 3..], 1, 8000).unwrap();
+
+        // 2 kHz is bin 64, and the bins are shown from 0 Hz up to and including 2 kHz
+        assert_eq!((image.width, image.height), (8, 65));
+        let column = |x: usize| -> Vec<u8> {
+            (0..65)
+                .map(|y| image.data[y * 8 + x])
+                .rev()
+                .collect::<Vec<u8>>()
+        };
+        // Only four columns have been computed so far, they are on the right
+        assert!(column(3).iter().all(|&level| level == 0));
+        let loudest = column(4)
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, level)| **level)
+            .map(|(bin, _)| bin);
+        assert_eq!(loudest, Some(32));
+        // A full scale sine is 0 dBFS. The Hann window spreads it over neighbouring bins, at half
+        // the amplitude.
+        assert!(column(7)[32] > 250, "{:?}", column(7));
+        assert!((column(7)[31] as i32 - 239).abs() < 3, "{:?}", column(7));
+        assert!(column(7)[20] < 100, "{:?}", column(7));
+    }
+
+    #[test]
+    fn test_stereo_is_mixed_down() {
+        let mut spectrogram = Spectrogram::new(CONFIG);
+        let left = sine(1000.0, 8000, 256 * 4);
+        let stereo: Vec<f32> = left.iter().flat_map(|&sample| [sample, -sample]).collect();
+        let image = spectrogram.push(&stereo, 2, 8000).unwrap();
+        // The channels cancel out
+        assert!(image.data.iter().all(|&level| level == 0));
+    }
+
+    #[test]
+    fn test_image_every_step() {
+        // Frames that don't divide the window add a varying number of columns per push
+        let mut spectrogram = Spectrogram::new(CONFIG);
+        let images = (0..100)
+            .filter(|_| spectrogram.push(&[0.5; 300], 1, 8000).is_some())
+            .count();
+        assert_eq!(images, 300 * 100 / (256 * 4));
+    }
+
+    #[test]
+    fn test_sample_rate_change() {
+        let mut spectrogram = Spectrogram::new(CONFIG);
+        assert!(spectrogram.push(&[0.5; 256 * 4], 1, 8000).is_some());
+        assert_eq!(spectrogram.push(&[0.5; 256 * 3], 1, 16000), None);
+        let image = spectrogram.push(&[0.5; 256], 1, 16000).unwrap();
+        // 2 kHz is bin 32 at 16 kHz
+        assert_eq!(image.height, 33);
+    }
+}
@@ -0,0 +1,201 @@
+//! Parsing of the recording start time from tag values and filenames.
+//!
+//! Timestamps are read as `YYYY-MM-DDTHH:MM[:SS[.fff]]`, optionally followed by `Z` or a UTC
+//! offset such as `+02:00`, and are treated as UTC when there is no offset. To allow them in
+//! filenames the time may be separated by `-` instead of `:`, and the date from the time by a
+//! space or `_` instead of `T`.
+
+const NS_PER_S: i64 = 1_000_000_000;
+
+/// Parse a timestamp to nanoseconds since the Unix epoch. The whole string must be a timestamp.
+pub fn parse_timestamp(text: &str) -> Option<u64> {
+    let mut parser = Parser(text.trim().as_bytes());
+    let ns = parser.timestamp()?;
+    parser.0.is_empty().then_some(ns)
+}
+
+/// Parse a count of seconds since the Unix epoch, e.g. `1740830400.5`.
+pub fn parse_unix_seconds(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return None;
+    }
+    let seconds: f64 = text.parse().ok()?;
+    Some((seconds * NS_PER_S as f64).round() as u64)
+}
+
+/// Find a timestamp in a file path, e.g. `recordings/2025-03-01T12-00-00.mp3`. Only the file name
+/// is searched, and the first timestamp found is returned.
+pub fn find_in_filename(path: &str) -> Option<u64> {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    (0..name.len()).find_map(|i| Parser(&name.as_bytes()[i..]).timestamp())
+}
+
+struct Parser<'a>(&'a [u8]);
+
+impl Parser<'_> {
+    fn timestamp(&mut self) -> Option<u64> {
+        let year = self.digits(4)?;
+        self.expect(b"-")?;
+        let month = self.digits(2)?;
+        self.expect(b"-")?;
+        let day = self.digits(2)?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        self.expect(b"T _")?;
+        let hour = self.digits(2)?;
+        let separator = self.expect(b":-")?;
+        let minute = self.digits(2)?;
+        let mut second = 0;
+        let mut nanos = 0;
+        if self.0.first() == Some(&separator) && self.0.get(1).is_some_and(u8::is_ascii_digit) {
+            self.0 = &self.0[1..];
+            second = self.digits(2)?;
+            if self.0.first() == Some(&b'.') {
+                self.0 = &self.0[1..];
+                let len = self.0.iter().take_while(|b| b.is_ascii_digit()).count();
+                let (fraction, rest) = self.0.split_at(len);
+                for (i, &digit) in fraction.iter().take(9).enumerate() {
+                    nanos += (digit - b'0') as i64 * 10i64.pow(8 - i as u32);
+                }
+                self.0 = rest;
+            }
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        let offset = self.utc_offset();
+
+        let days = days_from_civil(year, month, day);
+        let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+        u64::try_from(seconds * NS_PER_S + nanos).ok()
+    }
+
+    /// Read an optional `Z`, `+HH:MM` or `+HHMM` suffix, returning the offset in seconds.
+    fn utc_offset(&mut self) -> i64 {
+        let sign = match self.0.first() {
+            Some(b'Z') => {
+                self.0 = &self.0[1..];
+                return 0;
+            }
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            _ => return 0,
+        };
+        let mut parser = Parser(&self.0[1..]);
+        let Some(hours) = parser.digits(2) else {
+            return 0;
+        };
+        if parser.0.first() == Some(&b':') {
+            parser.0 = &parser.0[1..];
+        }
+        let minutes = parser.digits(2).unwrap_or(0);
+        self.0 = parser.0;
+        sign * (hours * 3600 + minutes * 60)
+    }
+
+    fn digits(&mut self, len: usize) -> Option<i64> {
+        let digits = self.0.get(..len)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        self.0 = &self.0[len..];
+        Some(digits.iter().fold(0, |n, &d| n * 10 + (d - b'0') as i64))
+    }
+
+    /// Consume one of the `allowed` separator bytes, returning it.
+    fn expect(&mut self, allowed: &[u8]) -> Option<u8> {
+        let (&first, rest) = self.0.split_first()?;
+        allowed.contains(&first).then(|| {
+            self.0 = rest;
+            first
+        })
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The number of days from 1970-01-01 to the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Count years from March so the leap day is at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARCH_1_2025: u64 = 1_740_787_200 * NS_PER_S as u64;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let noon = MARCH_1_2025 + 12 * 3600 * NS_PER_S as u64;
+        assert_eq!(parse_timestamp("2025-03-01T12:00"), Some(noon));
+        assert_eq!(parse_timestamp("2025-03-01T12:00:00Z"), Some(noon));
+        assert_eq!(
+            parse_timestamp("2025-03-01 12:00:00.25"),
+            Some(noon + 250_000_000)
+        );
+        assert_eq!(
+            parse_timestamp("2025-03-01T14:00:00+02:00"),
+            Some(noon),
+            "the offset is removed to get UTC"
+        );
+        assert_eq!(parse_timestamp("2025-03-01T11:30-0030"), Some(noon));
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00"), Some(0));
+        assert_eq!(
+            parse_timestamp("2024-02-29T00:00"),
+            Some(1_709_164_800 * NS_PER_S as u64)
+        );
+
+        // Dates without a time of day aren't precise enough to use as a start time
+        assert_eq!(parse_timestamp("2025-03-01"), None);
+        assert_eq!(parse_timestamp("2025"), None);
+        assert_eq!(parse_timestamp("2025-02-29T00:00"), None);
+        assert_eq!(parse_timestamp("2025-03-01T24:00"), None);
+        assert_eq!(parse_timestamp("2025-03-01T12:00 trailing"), None);
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59"), None);
+    }
+
+    #[test]
+    fn test_parse_unix_seconds() {
+        assert_eq!(
+            parse_unix_seconds("1740830400.5"),
+            Some(1_740_830_400_500_000_000)
+        );
+        assert_eq!(parse_unix_seconds(" 0 "), Some(0));
+        assert_eq!(parse_unix_seconds("-1"), None);
+        assert_eq!(parse_unix_seconds("2025-03-01T12:00"), None);
+        assert_eq!(parse_unix_seconds(""), None);
+    }
+
+    #[test]
+    fn test_find_in_filename() {
+        let noon = MARCH_1_2025 + 12 * 3600 * NS_PER_S as u64;
+        assert_eq!(find_in_filename("2025-03-01T12-00-00.mp3"), Some(noon));
+        assert_eq!(
+            find_in_filename("/data/robot_2025-03-01_12-00-00_mic.mp3"),
+            Some(noon)
+        );
+        assert_eq!(
+            find_in_filename("C:\\logs\\run 2025-03-01 12:00.mp3"),
+            Some(noon)
+        );
+        assert_eq!(find_in_filename("2025-03-01T12-00-00/audio.mp3"), None);
+        assert_eq!(find_in_filename("track-2025.mp3"), None);
+        assert_eq!(find_in_filename("énregistrement.mp3"), None);
+    }
+}
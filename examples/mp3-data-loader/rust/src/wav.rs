@@ -0,0 +1,430 @@
+//! Reading of uncompressed WAV files.
+//!
+//! A WAV file is a RIFF container holding a `fmt ` chunk describing the samples and a `data` chunk
+//! of interleaved samples. Integer samples of 8 to 32 bits and 32 or 64 bit float samples are
+//! supported, in plain and `WAVE_FORMAT_EXTENSIBLE` files.
+//!
+//! RIFF chunk sizes are 32 bits, so files over 4 GiB use the RF64 (or BW64) variant, where a
+//! `ds64` chunk holds the real sizes. Recorders that were stopped before finishing the file can
+//! leave the data size unset, in which case the samples run to the end of the file.
+//!
+//! Every sample takes the same number of bytes, so the offset of any sample can be computed and
+//! the file doesn't need to be scanned.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{AudioDecoder, FrameFormat};
+
+/// The number of samples per channel decoded at a time. WAV files have no frames, the audio is
+/// split into frames of this many samples to be indexed and batched like compressed audio.
+pub const FRAME_LEN: u64 = 1024;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Chunk sizes set to this in an RF64 file are read from the `ds64` chunk
+const RF64_SIZE: u32 = 0xffff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    /// Signed integer samples of the given number of bits, or unsigned for 8 bits
+    Int(u16),
+    /// IEEE float samples of 32 or 64 bits
+    Float(u16),
+}
+
+/// The layout of the samples in a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WavFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+    /// The number of bytes holding one sample of every channel
+    pub block_align: u16,
+    /// The byte offset of the first sample
+    pub data_offset: u64,
+    /// The length of the sample data in bytes
+    pub data_len: u64,
+}
+
+impl WavFormat {
+    /// Read the format and the position of the samples from the chunks of a WAV file.
+    pub fn read(mut reader: impl Read + Seek) -> io::Result<Self> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        let rf64 = match &header[..4] {
+            b"RIFF" => false,
+            b"RF64" | b"BW64" => true,
+            _ => return Err(invalid_data("not a RIFF file")),
+        };
+        if &header[8..] != b"WAVE" {
+            return Err(invalid_data("not a WAVE file"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        // The data size from the ds64 chunk of an RF64 file
+        let mut ds64_data_len = None;
+        let mut offset = 12;
+        while data.is_none() || format.is_none() {
+            let mut chunk = [0u8; 8];
+            match reader.read_exact(&mut chunk) {
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                result => result?,
+            }
+            let id = &chunk[..4];
+            let size = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+            let body = offset + 8;
+            match id {
+                b"ds64" if rf64 => {
+                    let body = read_body(&mut reader, size, file_len.saturating_sub(body), 16)?;
+                    ds64_data_len = Some(u64::from_le_bytes(body[8..16].try_into().unwrap()));
+                }
+                b"fmt " => {
+                    let body = read_body(&mut reader, size, file_len.saturating_sub(body), 16)?;
+                    format = Some(parse_fmt(&body)?);
+                }
+                b"data" => {
+                    let len = match size {
+                        RF64_SIZE if rf64 => ds64_data_len,
+                        // Left unset by a recorder that didn't finish writing the file
+                        0 | RF64_SIZE => None,
+                        size => Some(size as u64),
+                    };
+                    let len = len.unwrap_or(u64::MAX).min(file_len.saturating_sub(body));
+                    data = Some((body, len));
+                    if len == file_len - body {
+                        // Nothing can follow data that runs to the end of the file
+                        break;
+                    }
+                }
+                _ => (),
+            }
+            let len = match (id, data) {
+                (b"data", Some((_, len))) => len,
+                _ => size as u64,
+            };
+            // Chunks are padded to an even length
+            offset = body + len + len % 2;
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+
+        let (channels, sample_rate, sample_format, block_align) =
+            format.ok_or_else(|| invalid_data("missing fmt chunk"))?;
+        let (data_offset, data_len) = data.ok_or_else(|| invalid_data("missing data chunk"))?;
+        Ok(Self {
+            channels,
+            sample_rate,
+            sample_format,
+            block_align,
+            data_offset,
+            data_len,
+        })
+    }
+
+    /// The number of samples per channel in the file. A partial sample at the end is ignored.
+    pub fn sample_count(&self) -> u64 {
+        self.data_len / self.block_align as u64
+    }
+
+    /// The byte offset of the sample at `index`, counting samples per channel.
+    pub fn offset_of(&self, index: u64) -> u64 {
+        self.data_offset + index * self.block_align as u64
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        match self.sample_format {
+            SampleFormat::Int(bits) | SampleFormat::Float(bits) => bits.div_ceil(8) as usize,
+        }
+    }
+}
+
+/// Parse a `fmt ` chunk into the channel count, sample rate, sample format and block alignment.
+fn parse_fmt(body: &[u8]) -> io::Result<(u16, u32, SampleFormat, u16)> {
+    let u16_at = |pos: usize| u16::from_le_bytes([body[pos], body[pos + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+    let block_align = u16_at(12);
+    let bits = u16_at(14);
+    // The extensible format holds the real format code in the first two bytes of a GUID
+    if tag == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let sample_format = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8 | 16 | 24 | 32) => SampleFormat::Int(bits),
+        (WAVE_FORMAT_IEEE_FLOAT, 32 | 64) => SampleFormat::Float(bits),
+        _ => {
+            return Err(invalid_data(&format!(
+                "unsupported sample format {tag:#x} with {bits} bits"
+            )));
+        }
+    };
+    if channels == 0
+        || sample_rate == 0
+        || (block_align as usize) < channels as usize * bits as usize / 8
+    {
+        return Err(invalid_data("invalid fmt chunk"));
+    }
+    Ok((channels, sample_rate, sample_format, block_align))
+}
+
+/// Read a chunk body of `size` bytes, which must be at least `min_len` and fit in the `remaining`
+/// bytes of the file.
+fn read_body(
+    reader: &mut impl Read,
+    size: u32,
+    remaining: u64,
+    min_len: usize,
+) -> io::Result<Vec<u8>> {
+    // Check the size before allocating, a corrupt size could be up to 4 GiB
+    if size as u64 > remaining {
+        return Err(invalid_data("chunk runs past the end of the file"));
+    }
+    if (size as usize) < min_len {
+        return Err(invalid_data("chunk too short"));
+    }
+    let mut body = vec![0u8; size as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid WAV file: {msg}"),
+    )
+}
+
+/// Decodes the samples of a WAV file, [`FRAME_LEN`] samples per channel at a time.
+pub struct WavDecoder<R> {
+    reader: R,
+    format: WavFormat,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> WavDecoder<R> {
+    /// Create a decoder reading samples from `reader`, which should be positioned at the start of
+    /// a sample and end at the end of the data chunk.
+    pub fn new(reader: R, format: WavFormat) -> Self {
+        Self {
+            reader,
+            format,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> AudioDecoder for WavDecoder<R> {
+    fn decode(&mut self, samples: &mut Vec<f32>) -> anyhow::Result<Option<FrameFormat>> {
+        let block_align = self.format.block_align as usize;
+        self.buffer.resize(FRAME_LEN as usize * block_align, 0);
+        let mut len = 0;
+        while len < self.buffer.len() {
+            match self.reader.read(&mut self.buffer[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        let blocks = &self.buffer[..len - len % block_align];
+        if blocks.is_empty() {
+            return Ok(None);
+        }
+
+        let channels = self.format.channels as usize;
+        let width = self.format.bytes_per_sample();
+        samples.clear();
+        for block in blocks.chunks_exact(block_align) {
+            for sample in block.chunks_exact(width).take(channels) {
+                samples.push(decode_sample(self.format.sample_format, sample));
+            }
+        }
+        Ok(Some(FrameFormat {
+            channels,
+            sample_rate: self.format.sample_rate,
+        }))
+    }
+}
+
+/// Convert one little endian sample to a float in the range -1 to 1.
+fn decode_sample(format: SampleFormat, bytes: &[u8]) -> f32 {
+    match (format, bytes) {
+        (SampleFormat::Int(8), &[b]) => (b as f32 - 128.0) / 128.0,
+        (SampleFormat::Int(16), &[b0, b1]) => i16::from_le_bytes([b0, b1]) as f32 / 32768.0,
+        // Shift the sample to the top of an i32 to sign extend it
+        (SampleFormat::Int(24), &[b0, b1, b2]) => {
+            i32::from_le_bytes([0, b0, b1, b2]) as f32 / 2_147_483_648.0
+        }
+        (SampleFormat::Int(32), &[b0, b1, b2, b3]) => {
+            i32::from_le_bytes([b0, b1, b2, b3]) as f32 / 2_147_483_648.0
+        }
+        (SampleFormat::Float(32), &[b0, b1, b2, b3]) => f32::from_le_bytes([b0, b1, b2, b3]),
+        (SampleFormat::Float(64), bytes) => {
+            f64::from_le_bytes(bytes.try_into().unwrap_or_default()) as f32
+        }
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Build a WAV file from a `fmt ` chunk body and sample data.
+    fn wav(container: &[u8; 4], fmt: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunks = vec![];
+        chunks.extend(b"fmt ");
+        chunks.extend((fmt.len() as u32).to_le_bytes());
+        chunks.extend(fmt);
+        chunks.extend(b"LIST\x03\x00\x00\x00abc\x00");
+        chunks.extend(b"data");
+        chunks.extend((data.len() as u32).to_le_bytes());
+        chunks.extend(data);
+
+        let mut file = container.to_vec();
+        file.extend((chunks.len() as u32 + 4).to_le_bytes());
+        file.extend(b"WAVE");
+        file.extend(chunks);
+        file
+    }
+
+    fn pcm_fmt(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut fmt = vec![];
+        fmt.extend(tag.to_le_bytes());
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(sample_rate.to_le_bytes());
+        fmt.extend((sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend(block_align.to_le_bytes());
+        fmt.extend(bits.to_le_bytes());
+        fmt
+    }
+
+    fn decode_all(file: &[u8]) -> (WavFormat, Vec<f32>) {
+        let format = WavFormat::read(Cursor::new(file)).unwrap();
+        let data = &file[format.data_offset as usize..];
+        let mut decoder = WavDecoder::new(Cursor::new(data), format);
+        let mut all = vec![];
+        let mut samples = vec![];
+        while decoder.decode(&mut samples).unwrap().is_some() {
+            all.extend_from_slice(&samples);
+        }
+        (format, all)
+    }
+
+    #[test]
+    fn test_pcm16_stereo() {
+        let data: Vec<u8> = [0i16, 16384, -32768, 32767]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let file = wav(b"RIFF", &pcm_fmt(WAVE_FORMAT_PCM, 2, 48_000, 16), &data);
+        let (format, samples) = decode_all(&file);
+        assert_eq!(format.channels, 2);
+        assert_eq!(format.sample_rate, 48_000);
+        assert_eq!(format.sample_format, SampleFormat::Int(16));
+        assert_eq!(format.sample_count(), 2);
+        assert_eq!(samples, vec![0.0, 0.5, -1.0, 32767.0 / 32768.0]);
+    }
+
+    #[test]
+    fn test_sample_formats() {
+        let file = wav(
+            b"RIFF",
+            &pcm_fmt(WAVE_FORMAT_PCM, 1, 8000, 8),
+            &[128, 0, 192],
+        );
+        assert_eq!(decode_all(&file).1, vec![0.0, -1.0, 0.5]);
+
+        let file = wav(
+            b"RIFF",
+            &pcm_fmt(WAVE_FORMAT_PCM, 1, 8000, 24),
+            &[0x00, 0x00, 0xc0, 0x00, 0x00, 0x40],
+        );
+        assert_eq!(decode_all(&file).1, vec![-0.5, 0.5]);
+
+        let data: Vec<u8> = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let file = wav(
+            b"RIFF",
+            &pcm_fmt(WAVE_FORMAT_IEEE_FLOAT, 1, 8000, 32),
+            &data,
+        );
+        assert_eq!(decode_all(&file).1, vec![0.25, -0.75]);
+
+        let data: Vec<u8> = [0.25f64, -0.75]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let file = wav(
+            b"RIFF",
+            &pcm_fmt(WAVE_FORMAT_IEEE_FLOAT, 1, 8000, 64),
+            &data,
+        );
+        assert_eq!(decode_all(&file).1, vec![0.25, -0.75]);
+
+        // WAVE_FORMAT_EXTENSIBLE with the float subformat
+        let mut fmt = pcm_fmt(WAVE_FORMAT_EXTENSIBLE, 1, 8000, 32);
+        fmt.extend([22, 0, 32, 0, 4, 0, 0, 0]);
+        fmt.extend(WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        fmt.extend([0; 14]);
+        let data: Vec<u8> = [0.125f32].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let file = wav(b"RIFF", &fmt, &data);
+        assert_eq!(decode_all(&file).1, vec![0.125]);
+
+        let file = wav(b"RIFF", &pcm_fmt(0x55, 1, 8000, 16), &[]);
+        assert!(WavFormat::read(Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn test_rf64() {
+        let fmt = pcm_fmt(WAVE_FORMAT_PCM, 1, 8000, 16);
+        let data: Vec<u8> = (0..10i16).flat_map(|s| s.to_le_bytes()).collect();
+        let mut file = b"RF64\xff\xff\xff\xffWAVE".to_vec();
+        file.extend(b"ds64");
+        file.extend(28u32.to_le_bytes());
+        file.extend(0u64.to_le_bytes()); // RIFF size
+        file.extend((data.len() as u64).to_le_bytes());
+        file.extend(10u64.to_le_bytes()); // sample count
+        file.extend(0u32.to_le_bytes()); // table length
+        file.extend(b"fmt ");
+        file.extend((fmt.len() as u32).to_le_bytes());
+        file.extend(&fmt);
+        file.extend(b"data\xff\xff\xff\xff");
+        let data_offset = file.len() as u64;
+        file.extend(&data);
+        // A chunk after the data, which the ds64 size keeps out of the samples
+        file.extend(b"junk\x02\x00\x00\x00ab");
+
+        let format = WavFormat::read(Cursor::new(&file)).unwrap();
+        assert_eq!(format.data_offset, data_offset);
+        assert_eq!(format.data_len, 20);
+        assert_eq!(format.offset_of(3), data_offset + 6);
+    }
+
+    #[test]
+    fn test_unfinished_file() {
+        // The data size is left at zero, and the recording was cut off mid-sample
+        let mut file = wav(b"RIFF", &pcm_fmt(WAVE_FORMAT_PCM, 2, 8000, 16), &[]);
+        file.extend([0; 4 * 1500 + 3]);
+        let (format, samples) = decode_all(&file);
+        assert_eq!(format.sample_count(), 1500);
+        assert_eq!(samples.len(), 2 * 1500);
+    }
+
+    #[test]
+    fn test_chunk_past_end_of_file() {
+        // A corrupt fmt chunk size is rejected instead of allocated
+        let mut file = wav(b"RIFF", &pcm_fmt(WAVE_FORMAT_PCM, 1, 8000, 16), &[0; 4]);
+        file[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = WavFormat::read(Cursor::new(&file)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -3,10 +3,16 @@ import { Experimental } from "@foxglove/extension";
 // Import the .wasm file as a base64 data URL to be bundled with the extension
 import wasmUrl from "../rust/target/wasm32-unknown-unknown/release/foxglove_data_loader.wasm";
 
+// The loader detects the format from the file contents, so every format shares the same wasm
+// module.
+const SUPPORTED_FILE_TYPES = [".mp3", ".wav", ".flac", ".ogg"];
+
 export function activate(extensionContext: Experimental.ExtensionContext): void {
-  extensionContext.registerDataLoader({
-    type: "file",
-    wasmUrl,
-    supportedFileType: ".mp3",
-  });
+  for (const supportedFileType of SUPPORTED_FILE_TYPES) {
+    extensionContext.registerDataLoader({
+      type: "file",
+      wasmUrl,
+      supportedFileType,
+    });
+  }
 }
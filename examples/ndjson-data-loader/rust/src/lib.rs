@@ -3,30 +3,62 @@
 //! {"type":"temperature","time":0,"ambient":21,"cpu0":70,"cpu1":65,"cpu2":68,"cpu3":72}
 //! {"type":"accelerometer","time":0,"x":0,"y":0.00175,"z":0.17936678638491532}
 //!
-//! The loader stores the records in memory and publishes /accelerometer and /temperature topics.
+//! The loader keeps the raw lines in memory and publishes a topic for each distinct name produced
+//! by [`TOPIC_TEMPLATE`], which by default gives /accelerometer and /temperature topics.
+//! Only the fields needed to index each line are parsed up front; the full record is deserialized
+//! when a message on its channel is requested.
+//! When several files are opened together (e.g. rotated logs `app.log.1`, `app.log.2`, ...) they
+//! are merged into one log, and lines repeated at the boundary between two files are dropped.
+//! Files compressed with gzip or zstd are detected from their magic bytes and decoded while they
+//! are read.
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
+use data_loader_utils::{ChannelTimeIndex, console, reader};
 use foxglove::Encode;
+use serde::{
+    Deserialize, Deserializer,
+    de::{DeserializeSeed, IgnoredAny, MapAccess, Visitor},
+};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
     io::{BufRead, BufReader},
     rc::Rc,
 };
 
 use foxglove_data_loader::{
     BackfillArgs, DataLoader, DataLoaderArgs, Initialization, Message, MessageIterator,
-    MessageIteratorArgs, console, reader,
+    MessageIteratorArgs,
 };
 
-// The ID for the /accelerometer channel
-const ACC_CHANNEL_ID: u16 = 1;
-// The ID for the /temperature channel
-const TEMP_CHANNEL_ID: u16 = 2;
+/// The template used to build a topic name for each line.
+///
+/// Text inside braces is replaced by the value of that field of the line, and nested fields are
+/// separated by dots. For example, lines like
+/// `{"source":{"robot":"r2","sensor":"imu"},"type":"accelerometer",...}` can be split into one
+/// topic per robot and sensor with `/{source.robot}/{source.sensor}`.
+///
+/// Every line on a topic must have the same type.
+const TOPIC_TEMPLATE: &str = "/{type}";
+
+// The first bytes of a gzip member
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// The first bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
 
 #[derive(Default)]
 struct NDJsonLoader {
-    path: String,
-    rows: Rc<Vec<Row>>,
+    paths: Vec<String>,
+    records: Rc<Vec<Record>>,
+    /// Indexes into `records` for each channel, by time
+    channel_records: ChannelTimeIndex<usize>,
+    /// The channels created from the topic template, by topic name
+    channels: BTreeMap<String, ChannelInfo>,
+}
+
+struct ChannelInfo {
+    id: u16,
+    kind: RowKind,
 }
 
 impl DataLoader for NDJsonLoader {
@@ -34,66 +66,95 @@ impl DataLoader for NDJsonLoader {
     type Error = anyhow::Error;
 
     fn new(args: DataLoaderArgs) -> Self {
-        let path = args
-            .paths
-            .first()
-            .unwrap_or_else(|| panic!["didn't receive a file path as input"])
-            .clone();
+        let DataLoaderArgs { paths } = args;
+        assert![!paths.is_empty(), "didn't receive a file path as input"];
         Self {
-            path,
+            paths,
             ..Self::default()
         }
     }
 
     fn initialize(&mut self) -> Result<Initialization, Self::Error> {
-        let lines = BufReader::new(reader::open(&self.path)).lines();
-        let mut rows: Vec<Row> = lines
-            .map(|rline| {
-                rline
-                    .and_then(|line| serde_json::from_str(&line).map_err(|err| err.into()))
-                    .map_err(|err| err.into())
-            })
-            .collect::<Result<Vec<Row>, Self::Error>>()?;
-        rows.sort_by(|a, b| {
-            f64::partial_cmp(&a.get_time(), &b.get_time()).expect("time comparison failed")
+        let template = TopicTemplate::parse(TOPIC_TEMPLATE)?;
+        let mut files = self
+            .paths
+            .iter()
+            .map(|path| read_lines(path, &template, &mut self.channels))
+            .collect::<Result<Vec<Vec<Record>>, Self::Error>>()?;
+        // Order the files by their first timestamp so rotated logs can be passed in any order.
+        files.sort_by(|a, b| {
+            let a_start = a.first().map_or(f64::INFINITY, |record| record.time);
+            let b_start = b.first().map_or(f64::INFINITY, |record| record.time);
+            f64::partial_cmp(&a_start, &b_start).expect("time comparison failed")
         });
-        let start_seconds = rows
+
+        // When a log is rotated the last lines of one file may be repeated at the start of the
+        // next. Collect the lines of each file that overlap the start of the following file.
+        let boundaries: Vec<HashSet<Box<str>>> = (0..files.len())
+            .map(|i| {
+                let (Some(previous), Some(first)) =
+                    (i.checked_sub(1).map(|p| &files[p]), files[i].first())
+                else {
+                    return HashSet::new();
+                };
+                previous
+                    .iter()
+                    .filter(|record| record.time >= first.time)
+                    .map(|record| record.text.clone())
+                    .collect()
+            })
+            .collect();
+
+        let mut duplicate_count = 0;
+        let mut records: Vec<Record> = vec![];
+        for (file, boundary) in files.into_iter().zip(boundaries) {
+            for record in file {
+                if boundary.contains(&record.text) {
+                    duplicate_count += 1;
+                    continue;
+                }
+                records.push(record);
+            }
+        }
+        if duplicate_count > 0 {
+            console::log(&format!["Skipped {duplicate_count} duplicate lines"]);
+        }
+
+        // Files may overlap in time, so sort the merged records. The sort is stable, so records
+        // with the same time stay in file order.
+        records.sort_by(|a, b| f64::partial_cmp(&a.time, &b.time).expect("time comparison failed"));
+        let start_seconds = records
             .first()
             .ok_or(anyhow!["failed to read first row"])?
-            .get_time();
-        let end_seconds = rows
+            .time;
+        let end_seconds = records
             .last()
             .ok_or(anyhow!["failed to read last row"])?
-            .get_time();
-        let temperature_count = rows
-            .iter()
-            .filter(|row| matches![row, Row::Temperature(_)])
-            .count();
-        let accelerometer_count = rows
-            .iter()
-            .filter(|row| matches![row, Row::Accelerometer(_)])
-            .count();
-
-        self.rows = Rc::new(rows);
-        console::log(&format![
-            "Temperature[{temperature_count}], Accelerometer[{accelerometer_count}]"
-        ]);
+            .time;
+        for (index, record) in records.iter().enumerate() {
+            self.channel_records
+                .insert(record.channel_id, seconds_to_nanos(record.time), index);
+        }
+        self.records = Rc::new(records);
 
         let mut init = Initialization::builder()
             .start_time(seconds_to_nanos(start_seconds))
             .end_time(seconds_to_nanos(end_seconds));
 
         let vec3_schema = init.add_encode::<Accelerometer>()?;
-        vec3_schema
-            .add_channel_with_id(ACC_CHANNEL_ID, "/accelerometer")
-            .expect("channel should be free")
-            .message_count(accelerometer_count as u64);
-
         let temp_schema = init.add_encode::<Temperature>()?;
-        temp_schema
-            .add_channel_with_id(TEMP_CHANNEL_ID, "/temperature")
-            .expect("channel should be free")
-            .message_count(temperature_count as u64);
+        for (topic, channel) in &self.channels {
+            let schema = match channel.kind {
+                RowKind::Accelerometer => &vec3_schema,
+                RowKind::Temperature => &temp_schema,
+            };
+            let count = self.channel_records.message_count(channel.id);
+            schema
+                .add_channel_with_id(channel.id, topic)
+                .expect("channel should be free")
+                .message_count(count as u64);
+            console::log(&format!["{topic}[{count}]"]);
+        }
 
         Ok(init.build())
     }
@@ -102,58 +163,82 @@ impl DataLoader for NDJsonLoader {
         &mut self,
         args: MessageIteratorArgs,
     ) -> Result<Self::MessageIterator, Self::Error> {
-        Ok(NDJsonIterator::open(self.rows.clone(), &args))
+        Ok(NDJsonIterator::open(
+            self.records.clone(),
+            &self.channel_records,
+            &args,
+        ))
     }
 
     fn get_backfill(&mut self, args: BackfillArgs) -> Result<Vec<Message>, Self::Error> {
-        let want_accelerometer = args.channels.contains(&ACC_CHANNEL_ID);
-        let want_temperature = args.channels.contains(&TEMP_CHANNEL_ID);
-
-        let mut backfill: Vec<Message> = vec![];
-        let search_start_index = self.rows[..]
-            .binary_search_by(|row| {
-                seconds_to_nanos(row.get_time())
-                    .partial_cmp(&args.time)
-                    .expect("time comparison failed")
-            })
-            .unwrap_or_else(|n| n);
-
-        if want_accelerometer {
-            let option_backfill_accelerometer = self.rows[..search_start_index]
-                .iter()
-                .rfind(|row| matches![row, Row::Accelerometer(_)]);
-            if let Some(Row::Accelerometer(accel)) = option_backfill_accelerometer {
-                backfill.push(accel.to_message());
-            }
-        }
-        if want_temperature {
-            let option_backfill_temperature = self.rows[..search_start_index]
-                .iter()
-                .rfind(|row| matches![row, Row::Temperature(_)]);
-            if let Some(Row::Temperature(temperature)) = option_backfill_temperature {
-                backfill.push(temperature.to_message());
-            }
-        }
-        Ok(backfill)
+        // The latest record at or before the backfill time is found from each channel's index.
+        self.channel_records
+            .latest(args.time, args.channels.iter().copied())
+            .into_iter()
+            .map(|(_, _, &index)| self.records[index].to_message())
+            .collect()
     }
 }
 
+/// Read every line of a file into records, sorted by time.
+///
+/// A channel is added to `channels` for each new topic name produced by the template.
+fn read_lines(
+    path: &str,
+    template: &TopicTemplate,
+    channels: &mut BTreeMap<String, ChannelInfo>,
+) -> Result<Vec<Record>, anyhow::Error> {
+    let mut records = open_decoded(path)?
+        .lines()
+        .map(|rline| Record::parse(rline?, template, channels))
+        .collect::<Result<Vec<Record>, anyhow::Error>>()?;
+    records.sort_by(|a, b| f64::partial_cmp(&a.time, &b.time).expect("time comparison failed"));
+    Ok(records)
+}
+
+/// Open a file, decompressing it if it starts with gzip or zstd magic bytes.
+fn open_decoded(path: &str) -> Result<Box<dyn BufRead>, anyhow::Error> {
+    let mut file = BufReader::new(reader::open(path));
+    let magic = file.fill_buf()?;
+    if magic.starts_with(&GZIP_MAGIC) {
+        // Rotated logs are often concatenated, so keep reading after the first gzip member.
+        let decoder = flate2::bufread::MultiGzDecoder::new(file);
+        return Ok(Box::new(BufReader::new(decoder)));
+    }
+    if magic.starts_with(&ZSTD_MAGIC) {
+        let decoder = ruzstd::decoding::StreamingDecoder::new(file)?;
+        return Ok(Box::new(BufReader::new(decoder)));
+    }
+    Ok(Box::new(file))
+}
+
 struct NDJsonIterator {
-    rows: Rc<Vec<Row>>,
-    index: usize,
-    start: u64,
-    end: u64,
-    channels: BTreeSet<u16>,
+    records: Rc<Vec<Record>>,
+    /// The indexes of the records to emit, in time order
+    indexes: std::vec::IntoIter<usize>,
 }
 
 impl NDJsonIterator {
-    fn open(rows: Rc<Vec<Row>>, args: &MessageIteratorArgs) -> Self {
+    fn open(
+        records: Rc<Vec<Record>>,
+        channel_records: &ChannelTimeIndex<usize>,
+        args: &MessageIteratorArgs,
+    ) -> Self {
+        // The records are read once in initialize, so seeking is a search over each requested
+        // channel's index rather than re-reading (and decompressing) the file from the beginning.
+        // The records are sorted by time, so merging the channels by record index keeps them in
+        // time order, and records on other channels are never visited.
+        let channels: BTreeSet<u16> = args.channels.iter().copied().collect();
+        let mut indexes: Vec<usize> = channels
+            .into_iter()
+            .filter_map(|channel_id| channel_records.channel(channel_id))
+            .flat_map(|index| index.range(args.start_time, args.end_time))
+            .map(|(_, &index)| index)
+            .collect();
+        indexes.sort_unstable();
         Self {
-            rows: rows.clone(),
-            index: 0,
-            start: args.start_time.unwrap_or(0),
-            end: args.end_time.unwrap_or(u64::MAX),
-            channels: args.channels.iter().copied().collect(),
+            records,
+            indexes: indexes.into_iter(),
         }
     }
 }
@@ -162,37 +247,209 @@ impl MessageIterator for NDJsonIterator {
     type Error = anyhow::Error;
 
     fn next(&mut self) -> Option<Result<Message, Self::Error>> {
-        loop {
-            let row = self.rows.get(self.index);
-            self.index += 1;
-            if let Some(time) = row.map(|r| seconds_to_nanos(r.get_time())) {
-                if time < self.start {
-                    continue;
-                }
-                if time > self.end {
-                    return None;
-                }
+        let index = self.indexes.next()?;
+        Some(self.records[index].to_message())
+    }
+}
+
+// floating point time in seconds to u64 nanoseconds
+fn seconds_to_nanos(time_seconds: f64) -> u64 {
+    (time_seconds * 1.0e9) as u64
+}
+
+/// A line of the file, holding only the fields needed to index it.
+struct Record {
+    time: f64, // in seconds
+    channel_id: u16,
+    text: Box<str>,
+}
+
+impl Record {
+    fn parse(
+        text: String,
+        template: &TopicTemplate,
+        channels: &mut BTreeMap<String, ChannelInfo>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(&text);
+        let fields = HeaderFields { template }.deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        let fields = serde_json::Value::Object(fields);
+        let header = RecordHeader::deserialize(&fields)?;
+        let topic = template.render(&fields)?;
+        let channel_id = match channels.get(&topic) {
+            Some(channel) if channel.kind == header.kind => channel.id,
+            Some(_) => bail!["topic {topic} has lines of more than one type"],
+            None => {
+                let id = u16::try_from(channels.len() + 1)
+                    .map_err(|_| anyhow!["topic template produced too many topics"])?;
+                let kind = header.kind;
+                channels.insert(topic, ChannelInfo { id, kind });
+                id
+            }
+        };
+        Ok(Self {
+            time: header.time,
+            channel_id,
+            text: text.into_boxed_str(),
+        })
+    }
+
+    fn to_message(&self) -> Result<Message, anyhow::Error> {
+        let row: Row = serde_json::from_str(&self.text)?;
+        Ok(row.to_message(self.channel_id))
+    }
+}
+
+/// A topic name template, see [`TOPIC_TEMPLATE`].
+struct TopicTemplate {
+    parts: Vec<TemplatePart>,
+    /// The top level fields that the template reads from
+    roots: BTreeSet<String>,
+}
+
+enum TemplatePart {
+    Text(String),
+    /// The path of a (possibly nested) field
+    Field(Vec<String>),
+}
+
+impl TopicTemplate {
+    fn parse(template: &str) -> Result<Self, anyhow::Error> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}') else {
+                bail!["unclosed '{{' in topic template {template}"];
             };
-            match row {
-                None => return None,
-                Some(Row::Accelerometer(accel)) => {
-                    if self.channels.contains(&ACC_CHANNEL_ID) {
-                        return Some(Ok(accel.to_message()));
+            if open > 0 {
+                parts.push(TemplatePart::Text(rest[..open].to_string()));
+            }
+            let path = &rest[open + 1..open + close];
+            if path.is_empty() {
+                bail!["empty field in topic template {template}"];
+            }
+            parts.push(TemplatePart::Field(
+                path.split('.').map(String::from).collect(),
+            ));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        let roots = parts
+            .iter()
+            .filter_map(|part| match part {
+                TemplatePart::Field(path) => Some(path[0].clone()),
+                TemplatePart::Text(_) => None,
+            })
+            .collect();
+        Ok(Self { parts, roots })
+    }
+
+    fn render(&self, value: &serde_json::Value) -> Result<String, anyhow::Error> {
+        let mut topic = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => topic.push_str(text),
+                TemplatePart::Field(path) => {
+                    let field = path
+                        .iter()
+                        .try_fold(value, |value, key| value.get(key))
+                        .ok_or_else(|| anyhow!["line is missing field {}", path.join(".")])?;
+                    match field {
+                        serde_json::Value::String(text) => topic.push_str(text),
+                        serde_json::Value::Number(number) => topic.push_str(&number.to_string()),
+                        serde_json::Value::Bool(boolean) => topic.push_str(&boolean.to_string()),
+                        _ => bail!["field {} can't be used in a topic name", path.join(".")],
                     }
                 }
-                Some(Row::Temperature(temperature)) => {
-                    if self.channels.contains(&TEMP_CHANNEL_ID) {
-                        return Some(Ok(temperature.to_message()));
-                    }
+            }
+        }
+        Ok(topic)
+    }
+}
+
+/// Reads the top level fields of a line that are needed to index it: its type and time, and the
+/// fields the topic template reads from. The other fields are skipped over without being parsed
+/// into values.
+struct HeaderFields<'a> {
+    template: &'a TopicTemplate,
+}
+
+impl<'de> DeserializeSeed<'de> for HeaderFields<'_> {
+    type Value = serde_json::Map<String, serde_json::Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for HeaderFields<'_> {
+    type Value = serde_json::Map<String, serde_json::Value>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut fields = serde_json::Map::new();
+        while let Some(key) = map.next_key_seed(HeaderKey {
+            template: self.template,
+        })? {
+            match key {
+                Some(key) => {
+                    fields.insert(key, map.next_value()?);
                 }
-            };
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
         }
+        Ok(fields)
     }
 }
 
-// floating point time in seconds to u64 nanoseconds
-fn seconds_to_nanos(time_seconds: f64) -> u64 {
-    (time_seconds * 1.0e9) as u64
+/// A key of a line, which is only kept if [`HeaderFields`] reads its value.
+struct HeaderKey<'a> {
+    template: &'a TopicTemplate,
+}
+
+impl<'de> DeserializeSeed<'de> for HeaderKey<'_> {
+    type Value = Option<String>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for HeaderKey<'_> {
+    type Value = Option<String>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a field name")
+    }
+
+    fn visit_str<E>(self, key: &str) -> Result<Self::Value, E> {
+        let read = matches!(key, "type" | "time") || self.template.roots.contains(key);
+        Ok(read.then(|| key.to_string()))
+    }
+}
+
+/// The fields of a line that are read up front, along with the fields used by the topic template.
+/// All other fields are skipped until the line is deserialized into a [`Row`].
+#[derive(Debug, serde::Deserialize)]
+struct RecordHeader {
+    #[serde(rename = "type")]
+    kind: RowKind,
+    time: f64, // in seconds
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+enum RowKind {
+    #[serde(rename = "accelerometer")]
+    Accelerometer,
+    #[serde(rename = "temperature")]
+    Temperature,
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -205,10 +462,10 @@ enum Row {
 }
 
 impl Row {
-    fn get_time(&self) -> f64 {
+    fn to_message(&self, channel_id: u16) -> Message {
         match self {
-            Row::Accelerometer(accel) => accel.time,
-            Row::Temperature(temperature) => temperature.time,
+            Row::Accelerometer(accel) => accel.to_message(channel_id),
+            Row::Temperature(temperature) => temperature.to_message(channel_id),
         }
     }
 }
@@ -232,13 +489,13 @@ struct Temperature {
 }
 
 impl Accelerometer {
-    fn to_message(&self) -> Message {
+    fn to_message(&self, channel_id: u16) -> Message {
         let time_nanos = seconds_to_nanos(self.time);
         let mut data = Vec::with_capacity(self.encoded_len().unwrap_or(0));
         self.encode(&mut data)
             .expect("failed to encode Accelerometer");
         Message {
-            channel_id: ACC_CHANNEL_ID,
+            channel_id,
             log_time: time_nanos,
             publish_time: time_nanos,
             data,
@@ -247,13 +504,13 @@ impl Accelerometer {
 }
 
 impl Temperature {
-    fn to_message(&self) -> Message {
+    fn to_message(&self, channel_id: u16) -> Message {
         let time_nanos = seconds_to_nanos(self.time);
         let mut data = Vec::with_capacity(self.encoded_len().unwrap_or(0));
         self.encode(&mut data)
             .expect("failed to encode Temperature");
         Message {
-            channel_id: TEMP_CHANNEL_ID,
+            channel_id,
             log_time: time_nanos,
             publish_time: time_nanos,
             data,
@@ -262,3 +519,170 @@ impl Temperature {
 }
 
 foxglove_data_loader::export!(NDJsonLoader);
+
+#[cfg(test)]
+mod tests {
+    use data_loader_utils::{
+        conformance,
+        harness::{Harness, count_by_channel},
+    };
+
+    use super::*;
+
+    const EXAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../example.ndjson");
+
+    #[test]
+    fn test_conformance() {
+        conformance::assert_conforms::<NDJsonLoader>(&[EXAMPLE]);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let harness = Harness::<NDJsonLoader>::open(&[EXAMPLE]).unwrap();
+        let info = harness.info();
+        assert_eq!(info.start_time, 0);
+        assert_eq!(info.end_time, 29_750_000_000);
+        let topics: Vec<&str> = info.channels.iter().map(|c| c.topic.as_str()).collect();
+        // Channels are numbered in the order their topics first appear in the file
+        assert_eq!(topics, vec!["/temperature", "/accelerometer"]);
+        for channel in &info.channels {
+            assert_eq!(channel.message_count, Some(120));
+            assert!(channel.schema_name.is_some());
+        }
+        let logs = console::take_logs();
+        assert!(logs.contains(&"/temperature[120]".to_string()));
+    }
+
+    #[test]
+    fn test_read_all() {
+        let mut harness = Harness::<NDJsonLoader>::open(&[EXAMPLE]).unwrap();
+        let messages = harness.all_messages().unwrap();
+        assert_eq!(messages.len(), 240);
+        let counts = count_by_channel(&messages);
+        assert_eq!(counts.values().copied().collect::<Vec<_>>(), vec![120, 120]);
+        assert!(
+            messages
+                .windows(2)
+                .all(|pair| pair[0].log_time <= pair[1].log_time)
+        );
+    }
+
+    #[test]
+    fn test_read_range() {
+        let mut harness = Harness::<NDJsonLoader>::open(&[EXAMPLE]).unwrap();
+        let temperature = harness.info().channel("/temperature").id;
+        let messages = harness
+            .messages(MessageIteratorArgs {
+                start_time: Some(1_000_000_000),
+                end_time: Some(1_500_000_000),
+                channels: vec![temperature],
+            })
+            .unwrap();
+        let times: Vec<u64> = messages.iter().map(|message| message.log_time).collect();
+        assert_eq!(times, vec![1_000_000_000, 1_250_000_000, 1_500_000_000]);
+        let first = Temperature {
+            time: 1.0,
+            ambient: 21.0,
+            cpu0: 74.0,
+            cpu1: 65.0,
+            cpu2: 68.0,
+            cpu3: 74.0,
+        };
+        assert_eq!(messages[0].data, first.to_message(temperature).data);
+    }
+
+    #[test]
+    fn test_backfill() {
+        let mut harness = Harness::<NDJsonLoader>::open(&[EXAMPLE]).unwrap();
+        let accelerometer = harness.info().channel("/accelerometer").id;
+        let temperature = harness.info().channel("/temperature").id;
+        let messages = harness
+            .backfill(1_100_000_000, vec![temperature, accelerometer])
+            .unwrap();
+        // One message for each channel, in channel id order
+        let expected = [
+            Temperature {
+                time: 1.0,
+                ambient: 21.0,
+                cpu0: 74.0,
+                cpu1: 65.0,
+                cpu2: 68.0,
+                cpu3: 74.0,
+            }
+            .to_message(temperature),
+            Accelerometer {
+                time: 1.0,
+                x: 0.055649796382606066,
+                y: 0.0001421487641803758,
+                z: 0.18911066219595557,
+            }
+            .to_message(accelerometer),
+        ];
+        assert_eq!(messages.len(), expected.len());
+        for (message, expected) in messages.iter().zip(&expected) {
+            assert_eq!(message.channel_id, expected.channel_id);
+            assert_eq!(message.log_time, expected.log_time);
+            assert_eq!(message.data, expected.data);
+        }
+
+        // Messages at the backfill time are included
+        let messages = harness.backfill(0, vec![temperature]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].log_time, 0);
+        assert!(harness.backfill(1_100_000_000, vec![]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_merge_rotated_files() {
+        let line = |time: f64| {
+            format!(
+                r#"{{"type":"temperature","time":{time},"ambient":21,"cpu0":1,"cpu1":2,"cpu2":3,"cpu3":4}}"#
+            )
+        };
+        let first = [line(0.0), line(1.0), line(2.0)].join("\n");
+        let second = [line(2.0), line(3.0)].join("\n");
+        reader::insert_file("app.log.2", second);
+        reader::insert_file("app.log.1", first);
+        let mut harness = Harness::<NDJsonLoader>::open(&["app.log.2", "app.log.1"]).unwrap();
+        assert_eq!(
+            harness.info().channel("/temperature").message_count,
+            Some(4)
+        );
+        let times: Vec<u64> = harness
+            .all_messages()
+            .unwrap()
+            .iter()
+            .map(|message| message.log_time)
+            .collect();
+        assert_eq!(times, vec![0, 1_000_000_000, 2_000_000_000, 3_000_000_000]);
+        assert!(console::take_logs().contains(&"Skipped 1 duplicate lines".to_string()));
+        conformance::assert_conforms::<NDJsonLoader>(&["app.log.2", "app.log.1"]);
+    }
+
+    #[test]
+    fn test_topic_template() {
+        let template = TopicTemplate::parse("/{source.robot}/{source.sensor}").unwrap();
+        let mut channels = BTreeMap::new();
+        let line = r#"{"source":{"robot":"r2","sensor":"imu"},"type":"accelerometer","time":1.5,"x":0,"y":[{"skipped":true}],"z":0}"#;
+        let record = Record::parse(line.into(), &template, &mut channels).unwrap();
+        assert_eq!(record.time, 1.5);
+        assert_eq!(channels["/r2/imu"].id, record.channel_id);
+
+        // Only the fields needed for the header are read up front
+        let mut deserializer = serde_json::Deserializer::from_str(line);
+        let fields = HeaderFields {
+            template: &template,
+        }
+        .deserialize(&mut deserializer)
+        .unwrap();
+        let keys: Vec<&str> = fields.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["source", "time", "type"]);
+
+        let line = r#"{"type":"accelerometer","time":1,"x":0,"y":0,"z":0}"#;
+        let Err(err) = Record::parse(line.into(), &template, &mut channels) else {
+            panic!("parsed a line without the template's fields");
+        };
+        assert_eq!(err.to_string(), "line is missing field source.robot");
+        assert!(Record::parse(format!("{line} x"), &template, &mut channels).is_err());
+    }
+}
@@ -2,10 +2,23 @@ import { Experimental } from "@foxglove/extension";
 
 import wasmUrl from "../rust/target/wasm32-unknown-unknown/release/example_foxglove_ndjson_data_loader.wasm";
 
+// The loader detects gzip and zstd compression from the file contents, so the compressed
+// variants share the same wasm module.
+const SUPPORTED_FILE_TYPES = [
+  ".ndjson",
+  ".ndjson.gz",
+  ".ndjson.zst",
+  ".jsonl",
+  ".jsonl.gz",
+  ".jsonl.zst",
+];
+
 export function activate(extensionContext: Experimental.ExtensionContext): void {
-  extensionContext.registerDataLoader({
-    type: "file",
-    wasmUrl,
-    supportedFileType: ".ndjson",
-  });
+  for (const supportedFileType of SUPPORTED_FILE_TYPES) {
+    extensionContext.registerDataLoader({
+      type: "file",
+      wasmUrl,
+      supportedFileType,
+    });
+  }
 }
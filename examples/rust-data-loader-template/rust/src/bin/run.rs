@@ -0,0 +1,17 @@
+//! Runs the loader natively on files and prints its initialization and messages as JSON lines, to
+//! debug it without installing the extension in Foxglove:
+//!
+//! ```sh
+//! cargo run --features runner -- --start 0 --end 1000000000 --channel /topic ../data.xyz
+//! ```
+//!
+//! See `--help` for every option.
+
+// The library is only built as a WebAssembly module, so the loader is compiled into the binary
+// from its source instead of linked. `export!` is left out of native builds for this.
+#[path = "../lib.rs"]
+mod loader;
+
+fn main() -> anyhow::Result<()> {
+    data_loader_utils::runner::main::<loader::MyDataLoader>()
+}
@@ -1,11 +1,14 @@
+// Natively, the loader is only used by `src/bin/run.rs` and the tests
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
 use anyhow::anyhow;
 
+use data_loader_utils::reader::{self, Reader};
 use foxglove_data_loader::{
     DataLoader, DataLoaderArgs, Initialization, Message, MessageIterator, MessageIteratorArgs,
-    reader::{self, Reader},
 };
 
-struct MyDataLoader {
+pub(crate) struct MyDataLoader {
     readers: Vec<Reader>,
 }
 
@@ -33,7 +36,7 @@ impl DataLoader for MyDataLoader {
     }
 }
 
-struct MyMessageIterator;
+pub(crate) struct MyMessageIterator;
 
 impl MessageIterator for MyMessageIterator {
     type Error = anyhow::Error;
@@ -43,4 +46,22 @@ impl MessageIterator for MyMessageIterator {
     }
 }
 
+// Only exported from the WebAssembly module, so that `src/bin/run.rs` can compile this file natively
+#[cfg(target_arch = "wasm32")]
 foxglove_data_loader::export!(MyDataLoader);
+
+#[cfg(test)]
+mod tests {
+    use data_loader_utils::conformance;
+
+    use super::*;
+
+    /// A file for the loader to read in tests, next to `package.json`
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../fixture");
+
+    #[test]
+    #[ignore = "needs a fixture file and an implementation of MyDataLoader"]
+    fn test_conformance() {
+        conformance::assert_conforms::<MyDataLoader>(&[FIXTURE]);
+    }
+}
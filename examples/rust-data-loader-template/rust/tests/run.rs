@@ -0,0 +1,26 @@
+//! Checks that the command line runner in `src/bin/run.rs` builds and runs. Run with
+//! `cargo test --features runner`.
+
+use std::process::Command;
+
+#[test]
+fn test_help() {
+    let output = Command::new(env!("CARGO_BIN_EXE_run"))
+        .arg("--help")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage: run"));
+}
+
+#[test]
+fn test_missing_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_run"))
+        .arg("missing.xyz")
+        .output()
+        .unwrap();
+    // An error is reported, rather than the loader panicking when it opens the file
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to open missing.xyz"), "{stderr}");
+}